@@ -1,4 +1,4 @@
-use std::{env, fs, path::PathBuf, process::Command};
+use std::{env, fs, process::Command};
 
 fn main() {
     let cwd = env::current_dir().expect("Failed to get current working directory");
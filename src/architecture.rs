@@ -1,9 +1,19 @@
-use strum::FromRepr;
+use std::borrow::Cow;
 
-#[derive(Debug, Clone, Copy, PartialEq, FromRepr)]
-#[cfg_attr(test, derive(strum::EnumIter))]
+use strum::{EnumIter, FromRepr, IntoEnumIterator};
+
+use crate::detect::pe::{ImageInfo, ImageKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromRepr, EnumIter)]
 #[repr(u16)]
 pub enum Architecture {
+    /// A machine value without a name here, carried as is.
+    ///
+    /// `IMAGE_FILE_MACHINE_UNKNOWN` is 0, so [`FromRepr`] maps it here as well.
+    Unknown(u16) = 0x0000,
+    /// Not a real machine, this is what the `native_machine` of `IsWow64Process2` reports
+    /// in some contexts and means "the host".
+    TargetHost = 0x0001,
     I386 = 0x014c,
     R3000 = 0x0162,
     R4000 = 0x0166,
@@ -31,33 +41,81 @@ pub enum Architecture {
     Cef = 0x0cef,
     Ebc = 0x0ebc,
     Amd64 = 0x8664,
+    RiscV32 = 0x5032,
+    RiscV64 = 0x5064,
+    RiscV128 = 0x5128,
+    LoongArch32 = 0x6232,
+    LoongArch64 = 0x6264,
     M32R = 0x9041,
+    /// Seen in objects and as a process machine type, ARM64EC images themselves carry
+    /// [`Architecture::Amd64`] in their headers.
+    Arm64Ec = 0xa641,
+    /// Seen in objects, ARM64X images themselves carry [`Architecture::Arm64`] in their headers.
+    Arm64X = 0xa64e,
     Arm64 = 0xaa64,
     Cee = 0xc0ee,
 }
 
-impl TryFrom<windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE> for Architecture {
-    type Error = ();
-    fn try_from(
-        value: windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE,
-    ) -> Result<Self, Self::Error> {
-        Self::from_repr(value.0).ok_or(())
+#[cfg(windows)]
+impl From<windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE> for Architecture {
+    fn from(value: windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE) -> Self {
+        value.0.into()
     }
 }
+#[cfg(windows)]
 impl From<Architecture> for windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE {
     fn from(val: Architecture) -> Self {
-        windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE(val as u16)
+        windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE(val.into())
     }
 }
-impl TryFrom<u16> for Architecture {
-    type Error = ();
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        Self::from_repr(value).ok_or(())
+impl From<u16> for Architecture {
+    fn from(value: u16) -> Self {
+        Self::from_repr(value).unwrap_or(Architecture::Unknown(value))
     }
 }
 impl From<Architecture> for u16 {
     fn from(val: Architecture) -> Self {
-        val as u16
+        // the discriminants again, kept in line with them by the `from_and_to_raw` test
+        match val {
+            Architecture::Unknown(value) => value,
+            Architecture::TargetHost => 0x0001,
+            Architecture::I386 => 0x014c,
+            Architecture::R3000 => 0x0162,
+            Architecture::R4000 => 0x0166,
+            Architecture::R10000 => 0x0168,
+            Architecture::WceMipsV2 => 0x0169,
+            Architecture::Alpha => 0x0184,
+            Architecture::Sh3 => 0x01a2,
+            Architecture::Sh3Dsp => 0x01a3,
+            Architecture::Sh3E => 0x01a4,
+            Architecture::Sh4 => 0x01a6,
+            Architecture::Sh5 => 0x01a8,
+            Architecture::Arm => 0x01c0,
+            Architecture::Thumb => 0x01c2,
+            Architecture::ArmNt => 0x01c4,
+            Architecture::Am33 => 0x01d3,
+            Architecture::PowerPc => 0x01f0,
+            Architecture::PowerPcFp => 0x01f1,
+            Architecture::Ia64 => 0x0200,
+            Architecture::Mips16 => 0x0266,
+            Architecture::Alpha64_Axp64 => 0x0284,
+            Architecture::MipsFpu => 0x0366,
+            Architecture::MipsFpu16 => 0x0466,
+            Architecture::Tricore => 0x0520,
+            Architecture::Cef => 0x0cef,
+            Architecture::Ebc => 0x0ebc,
+            Architecture::Amd64 => 0x8664,
+            Architecture::RiscV32 => 0x5032,
+            Architecture::RiscV64 => 0x5064,
+            Architecture::RiscV128 => 0x5128,
+            Architecture::LoongArch32 => 0x6232,
+            Architecture::LoongArch64 => 0x6264,
+            Architecture::M32R => 0x9041,
+            Architecture::Arm64Ec => 0xa641,
+            Architecture::Arm64X => 0xa64e,
+            Architecture::Arm64 => 0xaa64,
+            Architecture::Cee => 0xc0ee,
+        }
     }
 }
 impl std::fmt::Display for Architecture {
@@ -67,26 +125,365 @@ impl std::fmt::Display for Architecture {
             Architecture::I386 => f.write_str("x86"),
             Architecture::Amd64 => f.write_str("x64"),
             Architecture::Arm64 => f.write_str("ARM64"),
+            Architecture::Arm64Ec => f.write_str("ARM64EC"),
+            Architecture::Arm64X => f.write_str("ARM64X"),
+            Architecture::Unknown(value) => write!(f, "unknown ({value:#06x})"),
             _ => f.write_fmt(format_args!("{self:?}")),
         }
     }
 }
 
+/// The stable names of architectures, as written by serde and accepted by [`FromStr`], the
+/// first of each is the one written. Architectures not listed here go by their lowercased
+/// variant name, and unknown ones by their hex value, e.g. `0x1234`.
+///
+/// [`FromStr`]: std::str::FromStr
+const NAMES: &[(Architecture, &[&str])] = &[
+    (Architecture::I386, &["x86", "i386", "i686"]),
+    (Architecture::Amd64, &["x64", "amd64", "x86_64", "x86-64"]),
+    (Architecture::Arm64, &["arm64", "aarch64"]),
+    (Architecture::Arm64Ec, &["arm64ec"]),
+    (Architecture::Arm64X, &["arm64x"]),
+    (Architecture::ArmNt, &["arm", "armnt", "arm32"]),
+    // the Windows CE flavour, which would otherwise take the name of the one above
+    (Architecture::Arm, &["armle"]),
+    (Architecture::Ia64, &["ia64", "itanium"]),
+];
+
+impl Architecture {
+    /// The names the architecture is commonly known by, the stable one first, empty for those
+    /// without one.
+    pub fn names(self) -> &'static [&'static str] {
+        NAMES
+            .iter()
+            .find(|(arch, _)| *arch == self)
+            .map_or(&[], |(_, names)| names)
+    }
+
+    /// The stable name of the architecture, see [`NAMES`].
+    pub fn id(self) -> Cow<'static, str> {
+        if let Architecture::Unknown(value) = self {
+            return format!("{value:#06x}").into();
+        }
+        match self.names().first() {
+            Some(name) => (*name).into(),
+            None => format!("{self:?}").to_ascii_lowercase().into(),
+        }
+    }
+}
+
+impl std::str::FromStr for Architecture {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        if let Some((arch, _)) = NAMES
+            .iter()
+            .find(|(_, names)| names.contains(&name.as_str()))
+        {
+            return Ok(*arch);
+        }
+        if let Some(arch) = Architecture::iter()
+            .filter(|arch| !matches!(arch, Architecture::Unknown(_)))
+            .find(|arch| arch.id() == name)
+        {
+            return Ok(arch);
+        }
+        if let Some(Ok(value)) = name
+            .strip_prefix("0x")
+            .map(|hex| u16::from_str_radix(hex, 16))
+        {
+            return Ok(value.into());
+        }
+        Err(format!(
+            "unknown architecture `{s}`, expected a name such as x86, x64, arm64, arm64ec or arm, \
+             or a machine value such as 0xaa64"
+        ))
+    }
+}
+
+impl serde::Serialize for Architecture {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.id())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Architecture {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <Cow<'de, str>>::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl Architecture {
+    #[cfg(windows)]
     pub fn current() -> Self {
         crate::detect::current::get_current_sys_architecture()
     }
+
+    /// The architecture this tool was built for, as there is no Windows to ask.
+    #[cfg(not(windows))]
+    pub fn current() -> Self {
+        match std::env::consts::ARCH {
+            "x86" => Architecture::I386,
+            "aarch64" => Architecture::Arm64,
+            "arm" => Architecture::ArmNt,
+            _ => Architecture::Amd64,
+        }
+    }
+}
+
+/// The system images are run on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Host {
+    pub architecture: Architecture,
+    /// The Windows build number, e.g. 22631 for Windows 11 23H2.
+    pub build: u32,
+}
+
+impl Host {
+    /// Windows 11 21H2, the first release emulating x64 on ARM64.
+    pub const BUILD_X64_EMULATION: u32 = 22000;
+    /// Windows 11 24H2, the first release without ARM32 support on ARM64.
+    pub const BUILD_NO_ARM32: u32 = 26100;
+
+    pub fn current() -> Self {
+        Host {
+            architecture: Architecture::current(),
+            build: Self::current_build(),
+        }
+    }
+
+    #[cfg(windows)]
+    fn current_build() -> u32 {
+        crate::detect::current::get_current_os_build()
+    }
+
+    /// There is no Windows to ask, assume the latest build any rule depends on.
+    #[cfg(not(windows))]
+    fn current_build() -> u32 {
+        Self::BUILD_NO_ARM32
+    }
+}
+
+/// Parses `<architecture>[:<build>]`, e.g. `arm64` or `arm64:19045`. The build defaults to the
+/// build of the current system.
+impl std::str::FromStr for Host {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (architecture, build) = match s.split_once(':') {
+            Some((architecture, build)) => (
+                architecture,
+                build
+                    .parse()
+                    .map_err(|_| format!("invalid build number `{build}`"))?,
+            ),
+            None => (s, Self::current_build()),
+        };
+        Ok(Host {
+            architecture: architecture.parse()?,
+            build,
+        })
+    }
+}
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (build {})", self.architecture, self.build)
+    }
+}
+
+/// How an image runs on a host.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Native,
+    /// Code of another architecture the host CPU executes directly, through the WOW64 layer.
+    Wow64,
+    /// User-mode code of a foreign architecture, which the host runs through emulation.
+    Emulated,
+    /// The image will not load on the host at all.
+    Unsupported,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Verdict::Native => "native",
+            Verdict::Wow64 => "WOW64",
+            Verdict::Emulated => "emulated",
+            Verdict::Unsupported => "will not load on this host",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compatibility {
+    pub verdict: Verdict,
+    pub reason: &'static str,
+}
+
+impl Compatibility {
+    fn new(verdict: Verdict, reason: &'static str) -> Self {
+        Compatibility { verdict, reason }
+    }
+
+    /// How `image` runs on `host`.
+    pub fn of(image: &ImageInfo, host: &Host) -> Self {
+        use Architecture::*;
+
+        if image.kind == ImageKind::ResourceOnly {
+            return Self::new(
+                Verdict::Native,
+                "resource-only image, loads into any process",
+            );
+        }
+        if image.architecture == host.architecture {
+            return Self::new(Verdict::Native, "matches the host architecture");
+        }
+        if image.kind == ImageKind::Driver {
+            return Self::new(
+                Verdict::Unsupported,
+                "kernel drivers run neither emulated nor under WOW64",
+            );
+        }
+        match (host.architecture, image.architecture) {
+            (_, Ia64) => Self::new(
+                Verdict::Unsupported,
+                "Itanium images run on no current Windows",
+            ),
+            (Arm64, Arm64Ec | Arm64X) => {
+                Self::new(Verdict::Native, "ARM64EC code runs natively on ARM64")
+            }
+            (_, Unknown(_)) => Self::new(
+                Verdict::Unsupported,
+                "the machine type is not known to this tool",
+            ),
+            (Amd64, I386) => Self::new(Verdict::Wow64, "x86 runs under WOW64 on x64"),
+            (Arm64, I386) => Self::new(Verdict::Emulated, "x86 is emulated on ARM64"),
+            (Arm64, Amd64) if host.build >= Host::BUILD_X64_EMULATION => {
+                Self::new(Verdict::Emulated, "x64 is emulated on ARM64")
+            }
+            (Arm64, Amd64) => Self::new(
+                Verdict::Unsupported,
+                "x64 emulation on ARM64 requires Windows 11",
+            ),
+            (Arm64, Arm | Thumb | ArmNt) if host.build < Host::BUILD_NO_ARM32 => Self::new(
+                Verdict::Wow64,
+                "ARM32 runs under WOW64 on ARM64 before Windows 11 24H2",
+            ),
+            (Arm64, Arm | Thumb | ArmNt) => Self::new(
+                Verdict::Unsupported,
+                "ARM32 support was removed in Windows 11 24H2",
+            ),
+            _ => Self::new(
+                Verdict::Unsupported,
+                "the host can neither run nor emulate this architecture",
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use strum::IntoEnumIterator;
-
     use super::*;
 
+    #[test]
+    fn compatibility() {
+        let verdict = |arch, kind, host_arch, build| {
+            let image = ImageInfo {
+                architecture: arch,
+                kind,
+            };
+            let host = Host {
+                architecture: host_arch,
+                build,
+            };
+            Compatibility::of(&image, &host).verdict
+        };
+        use Architecture::*;
+        use ImageKind::*;
+        let cases = [
+            (Arm64, Code, Arm64, 22631, Verdict::Native),
+            (Amd64, Code, Arm64, 22631, Verdict::Emulated),
+            (Amd64, Code, Arm64, 19045, Verdict::Unsupported),
+            (I386, Code, Arm64, 19045, Verdict::Emulated),
+            (I386, Code, Amd64, 19045, Verdict::Wow64),
+            (ArmNt, Code, Arm64, 22631, Verdict::Wow64),
+            (ArmNt, Code, Arm64, 26100, Verdict::Unsupported),
+            (Ia64, Code, Amd64, 26100, Verdict::Unsupported),
+            (Arm64, Code, Amd64, 26100, Verdict::Unsupported),
+            (Amd64, ResourceOnly, Arm64, 22631, Verdict::Native),
+            (Amd64, Driver, Arm64, 22631, Verdict::Unsupported),
+            (I386, Driver, Amd64, 22631, Verdict::Unsupported),
+            (Arm64, Driver, Arm64, 22631, Verdict::Native),
+        ];
+        for (arch, kind, host_arch, build, expected) in cases {
+            assert_eq!(
+                verdict(arch, kind, host_arch, build),
+                expected,
+                "{arch} {kind:?} on {host_arch} build {build}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_host() {
+        let host: Host = "ARM64:19045".parse().unwrap();
+        assert_eq!(
+            host,
+            Host {
+                architecture: Architecture::Arm64,
+                build: 19045
+            }
+        );
+        let host: Host = "x64".parse().unwrap();
+        assert_eq!(host.architecture, Architecture::Amd64);
+        assert!("arm64:latest".parse::<Host>().is_err());
+        assert!("sparc".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn parse_and_id() {
+        for (name, expected) in [
+            ("x64", Architecture::Amd64),
+            ("AMD64", Architecture::Amd64),
+            ("x86_64", Architecture::Amd64),
+            ("aarch64", Architecture::Arm64),
+            ("ARM64EC", Architecture::Arm64Ec),
+            ("i386", Architecture::I386),
+            ("arm", Architecture::ArmNt),
+            ("riscv64", Architecture::RiscV64),
+            ("0x1234", Architecture::Unknown(0x1234)),
+        ] {
+            assert_eq!(name.parse::<Architecture>(), Ok(expected), "{name}");
+        }
+        assert!("sparc".parse::<Architecture>().is_err());
+        assert_eq!(Architecture::Amd64.id(), "x64");
+        assert_eq!(Architecture::Unknown(0x1234).id(), "0x1234");
+        for value in 0..=u16::MAX {
+            let arch = Architecture::from(value);
+            assert_eq!(arch.id().parse::<Architecture>(), Ok(arch), "{arch:?}");
+        }
+    }
+
+    #[test]
+    fn from_and_to_raw() {
+        for arch in Architecture::iter() {
+            assert_eq!(Architecture::from(u16::from(arch)), arch);
+        }
+        for value in 0..=u16::MAX {
+            assert_eq!(u16::from(Architecture::from(value)), value);
+        }
+        assert_eq!(Architecture::from(0xa641), Architecture::Arm64Ec);
+        assert_eq!(Architecture::from(0x5064), Architecture::RiscV64);
+        assert_eq!(Architecture::from(0x1234), Architecture::Unknown(0x1234));
+        assert_eq!(Architecture::from(0x1234).to_string(), "unknown (0x1234)");
+    }
+
+    #[cfg(windows)]
     #[test]
     fn from_and_to_windows() {
-        let mut theirs = [
+        let theirs = [
             windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_ALPHA,
             windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_ALPHA64,
             windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_AM33,
@@ -120,29 +517,18 @@ mod tests {
             windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_UNKNOWN,
             windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_WCEMIPSV2,
         ];
-        theirs.sort_by_key(|x| x.0);
-        let mut ours = Architecture::iter()
-            // Alpha64 and AXP64 are of the same value on our side, so we repeat them to keep the order
-            .chain([Architecture::Alpha64_Axp64])
-            .collect::<Vec<_>>();
-        ours.sort_by_key(|x| *x as u16);
-
-        // the first two are Unknown and TargetHost, which we don't support and expects an error
-        let ours = [None, None]
-            .into_iter()
-            .chain(ours.into_iter().map(Some))
-            .collect::<Vec<_>>();
-        for (their, our) in theirs.iter().zip(ours.iter()) {
-            let our_from_their: Result<Architecture, ()> = (*their).try_into();
-            if let Some(our) = our {
-                assert_eq!(our_from_their.unwrap(), *our);
+        for their in theirs {
+            let our = Architecture::from(their);
+            if their == windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_UNKNOWN {
+                assert_eq!(our, Architecture::Unknown(0));
             } else {
-                assert!(our_from_their.is_err());
+                assert!(
+                    !matches!(our, Architecture::Unknown(_)),
+                    "{their:?} has no name"
+                );
             }
-        }
-        for our in ours.iter().filter_map(|x| x.as_ref().map(|x| *x)) {
-            let their: windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE = our.into();
-            assert_eq!(their.0, our.into());
+            let back: windows::Win32::System::SystemInformation::IMAGE_FILE_MACHINE = our.into();
+            assert_eq!(back, their);
         }
     }
 }
@@ -0,0 +1,479 @@
+//! Remember detection results between runs, so that files unchanged since the last scan are not
+//! opened again.
+//!
+//! Entries are keyed by absolute path and checked against the size, modification time and,
+//! where the platform's metadata carries one, the file ID. The whole cache is dropped when
+//! [`pe::DETECTOR_VERSION`] changes.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    architecture::{Architecture, Host},
+    detect::{
+        self,
+        pe::{self, ImageInfo},
+    },
+    executable, sibling,
+};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct File {
+    detector: u32,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    identity: Identity,
+    /// The image as seen by each host architecture it was detected for.
+    infos: Vec<HostInfo>,
+    #[serde(default)]
+    sha256: Option<String>,
+    /// The native build found installed alongside for each host it was found for.
+    #[serde(default)]
+    siblings: Vec<HostSibling>,
+}
+
+/// What tells whether a file changed since it was detected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Identity {
+    size: u64,
+    /// Nanoseconds since the Unix epoch.
+    mtime: u64,
+    /// The device or volume, and the index of the file on it.
+    file_id: Option<[u64; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct HostInfo {
+    host: Architecture,
+    #[serde(flatten)]
+    info: ImageInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HostSibling {
+    host: Architecture,
+    build: u32,
+    path: PathBuf,
+}
+
+/// What was detected of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detected {
+    /// The image as seen by each host, in the order given.
+    pub infos: Vec<ImageInfo>,
+    /// The SHA-256 of the file as lowercase hex, when asked for.
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    /// Entries loaded from disk, only read during a scan.
+    old: HashMap<String, Entry>,
+    /// Entries detected during this scan, from the worker threads.
+    new: Mutex<HashMap<String, Entry>>,
+}
+
+impl Cache {
+    /// The default location, in the user's local cache directory.
+    pub fn default_path() -> Option<PathBuf> {
+        #[cfg(windows)]
+        let dir = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+        #[cfg(not(windows))]
+        let dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")));
+        Some(dir?.join("WoArchiTect").join("cache.json"))
+    }
+
+    /// Load the cache at `path`, empty if it does not exist yet or was written by another
+    /// detector version.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).context(JsonSnafu { path })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => File::default(),
+            Err(source) => {
+                return Err(Error::Io {
+                    source,
+                    path: path.into(),
+                });
+            }
+        };
+        let old = if file.detector == pe::DETECTOR_VERSION {
+            file.entries
+        } else {
+            HashMap::new()
+        };
+        Ok(Cache::new(path, old))
+    }
+
+    /// An empty cache to be saved to `path`.
+    pub fn empty(path: &Path) -> Self {
+        Cache::new(path, HashMap::new())
+    }
+
+    fn new(path: &Path, old: HashMap<String, Entry>) -> Self {
+        Cache {
+            path: path.into(),
+            old,
+            new: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Detect the image at `path` as seen by each of `hosts`, and hash it if `hash` is set,
+    /// from the cache if the file is unchanged.
+    ///
+    /// Failures are not cached, they may well be transient.
+    pub fn detect(
+        &self,
+        path: &Path,
+        hosts: &[Architecture],
+        hash: bool,
+    ) -> detect::Result<Detected> {
+        let Some((key, identity)) = key(path) else {
+            return detect_uncached(path, hosts, hash);
+        };
+        let old = self
+            .old
+            .get(&key)
+            .filter(|entry| entry.identity == identity);
+        if let Some(entry) = old
+            && let Some(infos) = entry.lookup(hosts)
+            && (!hash || entry.sha256.is_some())
+        {
+            self.new.lock().unwrap().insert(key, entry.clone());
+            return Ok(Detected {
+                infos,
+                sha256: entry.sha256.clone().filter(|_| hash),
+            });
+        }
+        let detected = detect_uncached(path, hosts, hash)?;
+        let mut entry = Entry {
+            identity,
+            infos: hosts
+                .iter()
+                .zip(&detected.infos)
+                .map(|(&host, &info)| HostInfo { host, info })
+                .collect(),
+            sha256: detected.sha256.clone(),
+            siblings: vec![],
+        };
+        // keep what other hosts saw of the unchanged file, its hash and its siblings
+        if let Some(old) = old {
+            entry
+                .infos
+                .extend(old.infos.iter().filter(|info| !hosts.contains(&info.host)));
+            entry.sha256 = entry.sha256.or_else(|| old.sha256.clone());
+            entry.siblings = old.siblings.clone();
+        }
+        self.new.lock().unwrap().insert(key, entry);
+        Ok(detected)
+    }
+
+    /// A native build of the binary at `path` for `host` installed alongside, see
+    /// [`sibling::find_native`], remembered with the detection of `path` in this scan.
+    ///
+    /// Only builds found are remembered, and detected again before they are reported, as
+    /// siblings are installed, replaced and removed without `path` changing.
+    pub fn native_alternative(&self, path: &Path, host: &Host) -> Option<PathBuf> {
+        let Some((key, identity)) = key(path) else {
+            return sibling::find_native(path, host);
+        };
+        let cached = self
+            .new
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|entry| entry.identity == identity)
+            .and_then(|entry| entry.sibling(host));
+        if let Some(found) = cached
+            && sibling::is_native(&found, host)
+        {
+            return Some(found);
+        }
+        let found = sibling::find_native(path, host);
+        if let Some(entry) = self.new.lock().unwrap().get_mut(&key) {
+            entry
+                .siblings
+                .retain(|sibling| (sibling.host, sibling.build) != (host.architecture, host.build));
+            entry.siblings.extend(found.iter().map(|found| HostSibling {
+                host: host.architecture,
+                build: host.build,
+                path: found.clone(),
+            }));
+        }
+        found
+    }
+
+    /// Write the cache back, with the entries of this scan and those of earlier scans whose
+    /// files still exist.
+    pub fn save(self) -> Result<()> {
+        let mut entries = self.new.into_inner().unwrap();
+        for (key, entry) in self.old {
+            if !entries.contains_key(&key) && Path::new(&key).exists() {
+                entries.insert(key, entry);
+            }
+        }
+        let file = File {
+            detector: pe::DETECTOR_VERSION,
+            entries,
+        };
+        let path = &self.path;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(IoSnafu { path })?;
+        }
+        // write aside and rename, so that an interrupted write does not leave a broken cache
+        let temp = path.with_extension("json.tmp");
+        let bytes = serde_json::to_vec(&file).context(JsonSnafu { path })?;
+        fs::write(&temp, bytes).context(IoSnafu { path: &temp })?;
+        fs::rename(&temp, path).context(IoSnafu { path })
+    }
+}
+
+impl Entry {
+    /// The infos for each of `hosts`, if all were detected before.
+    fn lookup(&self, hosts: &[Architecture]) -> Option<Vec<ImageInfo>> {
+        hosts
+            .iter()
+            .map(|&host| {
+                self.infos
+                    .iter()
+                    .find(|info| info.host == host)
+                    .map(|info| info.info)
+            })
+            .collect()
+    }
+
+    /// The native build found for `host` before.
+    fn sibling(&self, host: &Host) -> Option<PathBuf> {
+        self.siblings
+            .iter()
+            .find(|sibling| (sibling.host, sibling.build) == (host.architecture, host.build))
+            .map(|sibling| sibling.path.clone())
+    }
+}
+
+/// Detect the image at `path` through `cache` if there is one.
+pub fn detect(
+    cache: Option<&Cache>,
+    path: &Path,
+    hosts: &[Architecture],
+    hash: bool,
+) -> detect::Result<Detected> {
+    match cache {
+        Some(cache) => cache.detect(path, hosts, hash),
+        None => detect_uncached(path, hosts, hash),
+    }
+}
+
+/// Find a native build of the binary at `path` for `host` through `cache` if there is one.
+pub fn native_alternative(cache: Option<&Cache>, path: &Path, host: &Host) -> Option<PathBuf> {
+    match cache {
+        Some(cache) => cache.native_alternative(path, host),
+        None => sibling::find_native(path, host),
+    }
+}
+
+/// Detect the image at `path` as seen by each of `hosts`, and hash it if `hash` is set.
+///
+/// AnyCPU .NET assemblies take the architecture of the host, so this detects once per host.
+pub fn detect_uncached(
+    path: &Path,
+    hosts: &[Architecture],
+    hash: bool,
+) -> detect::Result<Detected> {
+    let infos = hosts
+        .iter()
+        .map(|&host| pe::detect_image_info_file(path, host))
+        .collect::<detect::Result<_>>()?;
+    let sha256 = hash.then(|| executable::sha256_file(path)).transpose()?;
+    Ok(Detected { infos, sha256 })
+}
+
+/// The key and identity of the file at `path`, `None` if its metadata cannot be read.
+fn key(path: &Path) -> Option<(String, Identity)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    #[cfg(unix)]
+    let file_id = {
+        use std::os::unix::fs::MetadataExt;
+        Some([metadata.dev(), metadata.ino()])
+    };
+    // the file index is only exposed through an open handle on Windows, which would defeat
+    // the purpose of not opening unchanged files
+    #[cfg(not(unix))]
+    let file_id = None;
+    let identity = Identity {
+        size: metadata.len(),
+        mtime: mtime.as_nanos().try_into().ok()?,
+        file_id,
+    };
+    let key = std::path::absolute(path)
+        .ok()?
+        .to_string_lossy()
+        .into_owned();
+    Some((key, identity))
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed accessing cache {}: {}", path.display(), source))]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("invalid cache {}: {}", path.display(), source))]
+    Json {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let cache_path = dir.join("cache.json");
+        let exe = dir.join("test.exe");
+        fs::copy(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/test_assets/testbin_i686-pc-windows-msvc.exe"
+            ),
+            &exe,
+        )
+        .unwrap();
+        let hosts = [Architecture::Amd64];
+
+        let cache = Cache::load(&cache_path).unwrap();
+        let detected = cache.detect(&exe, &hosts, false).unwrap();
+        assert_eq!(detected.infos[0].architecture, Architecture::I386);
+        assert_eq!(detected.sha256, None);
+        cache.save().unwrap();
+
+        // a stale entry is served while the identity matches, proving the file is not opened
+        let mut file: File = serde_json::from_slice(&fs::read(&cache_path).unwrap()).unwrap();
+        let entry = file.entries.values_mut().next().unwrap();
+        entry.infos[0].info.architecture = Architecture::Arm64;
+        fs::write(&cache_path, serde_json::to_vec(&file).unwrap()).unwrap();
+        let cache = Cache::load(&cache_path).unwrap();
+        assert_eq!(
+            cache.detect(&exe, &hosts, false).unwrap().infos[0].architecture,
+            Architecture::Arm64
+        );
+        // another host, or a hash not taken before, is detected afresh
+        assert_eq!(
+            cache
+                .detect(&exe, &[Architecture::Arm64], false)
+                .unwrap()
+                .infos[0]
+                .architecture,
+            Architecture::I386
+        );
+        let detected = cache.detect(&exe, &hosts, true).unwrap();
+        assert_eq!(detected.infos[0].architecture, Architecture::I386);
+        assert_eq!(
+            detected.sha256,
+            Some(executable::sha256_file(&exe).unwrap())
+        );
+
+        // a changed file is detected again
+        fs::write(&exe, b"MZ").unwrap();
+        assert!(cache.detect(&exe, &hosts, false).is_err());
+
+        // as is everything after a detector change
+        file.detector += 1;
+        fs::write(&cache_path, serde_json::to_vec(&file).unwrap()).unwrap();
+        assert!(Cache::load(&cache_path).unwrap().old.is_empty());
+
+        fs::write(&cache_path, "{").unwrap();
+        assert!(matches!(Cache::load(&cache_path), Err(Error::Json { .. })));
+    }
+
+    #[test]
+    fn test_native_alternative() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let cache_path = dir.join("cache.json");
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+        let emulated = dir.join("x64").join("tool.exe");
+        let native = dir.join("arm64").join("tool.exe");
+        fs::create_dir_all(emulated.parent().unwrap()).unwrap();
+        fs::create_dir_all(native.parent().unwrap()).unwrap();
+        fs::copy(assets.join("testbin_x86_64-pc-windows-msvc.exe"), &emulated).unwrap();
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+
+        let scan = || {
+            let cache = Cache::load(&cache_path).unwrap();
+            cache
+                .detect(&emulated, &[host.architecture], false)
+                .unwrap();
+            cache
+        };
+        let siblings = |cache: &Cache| {
+            let new = cache.new.lock().unwrap();
+            new.values().next().unwrap().siblings.clone()
+        };
+
+        // none found is not remembered, a build installed after the first scan is found
+        let cache = scan();
+        assert_eq!(cache.native_alternative(&emulated, &host), None);
+        assert_eq!(siblings(&cache), []);
+        cache.save().unwrap();
+        fs::copy(assets.join("testbin_aarch64-pc-windows-msvc.exe"), &native).unwrap();
+        let cache = scan();
+        assert_eq!(
+            cache.native_alternative(&emulated, &host),
+            Some(native.clone())
+        );
+        cache.save().unwrap();
+
+        // one found is remembered across scans
+        let cache = scan();
+        assert_eq!(
+            siblings(&cache),
+            [HostSibling {
+                host: host.architecture,
+                build: host.build,
+                path: native.clone(),
+            }]
+        );
+        assert_eq!(
+            cache.native_alternative(&emulated, &host),
+            Some(native.clone())
+        );
+
+        // but detected again, so that one replaced by a non-native build or removed is not
+        fs::copy(assets.join("testbin_i686-pc-windows-msvc.exe"), &native).unwrap();
+        assert_eq!(cache.native_alternative(&emulated, &host), None);
+        assert_eq!(siblings(&cache), []);
+        fs::copy(assets.join("testbin_aarch64-pc-windows-msvc.exe"), &native).unwrap();
+        assert_eq!(
+            cache.native_alternative(&emulated, &host),
+            Some(native.clone())
+        );
+        fs::remove_file(&native).unwrap();
+        assert_eq!(cache.native_alternative(&emulated, &host), None);
+    }
+}
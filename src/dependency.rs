@@ -0,0 +1,298 @@
+//! Resolve the DLL dependencies of an executable the way the Windows loader would, and check
+//! that every DLL found can actually be loaded into the process.
+
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{architecture::Architecture, detect};
+
+/// A node of the dependency tree.
+pub struct Dependency {
+    /// The DLL name as imported, or the file name for the root executable.
+    pub name: String,
+    pub delay_load: bool,
+    pub resolution: Resolution,
+    /// Failure reading the import tables of the resolved file, its dependencies are unknown.
+    pub error: Option<detect::Error>,
+    pub children: Vec<Dependency>,
+}
+
+pub enum Resolution {
+    /// An API set contract such as `api-ms-win-core-*`, mapped to a real DLL by the loader.
+    ApiSet,
+    Missing,
+    Found {
+        path: PathBuf,
+        architecture: detect::Result<Architecture>,
+        /// Whether the file can be loaded into a process of the root executable's architecture.
+        loadable: bool,
+        /// The file has already been expanded elsewhere in the tree, so its children are omitted.
+        repeated: bool,
+    },
+}
+
+impl Dependency {
+    /// Whether this dependency, or any of its transitive dependencies, is missing or cannot
+    /// be loaded.
+    pub fn has_problem(&self) -> bool {
+        let problem = match &self.resolution {
+            Resolution::ApiSet => false,
+            Resolution::Missing => true,
+            Resolution::Found {
+                architecture,
+                loadable,
+                ..
+            } => architecture.is_err() || !loadable,
+        };
+        problem || self.error.is_some() || self.children.iter().any(Self::has_problem)
+    }
+}
+
+/// Resolves DLLs against a Windows installation.
+pub struct Resolver {
+    root: PathBuf,
+    host: Architecture,
+    path_dirs: Vec<PathBuf>,
+}
+
+impl Resolver {
+    /// `root` is the directory containing the `Windows` directory, e.g. `C:\` or a mounted
+    /// image, and `host` is the native architecture of that installation.
+    pub fn new(root: PathBuf, host: Architecture, path_dirs: Vec<PathBuf>) -> Self {
+        Resolver {
+            root,
+            host,
+            path_dirs,
+        }
+    }
+
+    /// The native architecture of the installation.
+    pub fn host(&self) -> Architecture {
+        self.host
+    }
+
+    /// The root of the running system, i.e. `%SystemDrive%\`.
+    pub fn system_root() -> PathBuf {
+        let drive = std::env::var("SystemDrive").unwrap_or_else(|_| "C:".to_string());
+        PathBuf::from(format!("{drive}\\"))
+    }
+
+    /// The system directory a process of `arch` sees as `System32`, taking WOW64 file system
+    /// redirection into account.
+    pub fn system_dir(&self, arch: Architecture) -> PathBuf {
+        let windows = self.root.join("Windows");
+        match (self.host, arch) {
+            (Architecture::Amd64 | Architecture::Arm64, Architecture::I386) => {
+                windows.join("SysWOW64")
+            }
+            (Architecture::Arm64, Architecture::ArmNt) => windows.join("SysArm32"),
+            _ => windows.join("System32"),
+        }
+    }
+
+    /// The directories searched for a DLL, in the standard search order for desktop
+    /// applications with safe DLL search mode enabled.
+    ///
+    /// The current directory is not searched, as it is not known ahead of time.
+    pub fn search_dirs(&self, app_dir: &Path, arch: Architecture) -> Vec<PathBuf> {
+        let mut dirs = vec![app_dir.to_path_buf(), self.system_dir(arch)];
+        dirs.push(self.root.join("Windows"));
+        dirs.extend(self.path_dirs.iter().cloned());
+        dirs
+    }
+
+    pub fn resolve(&self, dll: &str, app_dir: &Path, arch: Architecture) -> Option<PathBuf> {
+        self.search_dirs(app_dir, arch)
+            .into_iter()
+            .map(|dir| dir.join(dll))
+            .find(|path| path.is_file())
+    }
+
+    /// Whether the DLL at `path`, of architecture `dll`, can be loaded into a process of
+    /// `process` architecture.
+    pub fn is_loadable(&self, process: Architecture, path: &Path, dll: Architecture) -> bool {
+        process == dll
+            // ARM64X DLLs, which is what System32 is made of on ARM64, carry the ARM64 machine
+            // type but also serve x64 processes through their ARM64EC half
+            || (self.host == Architecture::Arm64
+                && process == Architecture::Amd64
+                && dll == Architecture::Arm64
+                && detect::pe::is_hybrid_executable_file(path).unwrap_or(false))
+    }
+
+    /// Build the dependency tree of `exe`.
+    pub fn analyze(&self, exe: &Path) -> detect::Result<Dependency> {
+        let arch = detect::pe::detect_executable_architecture_file_for_host(exe, self.host)?;
+        let app_dir = exe.parent().unwrap_or(Path::new(""));
+        let mut expanded = HashSet::new();
+        let mut root = Dependency {
+            name: exe
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            delay_load: false,
+            resolution: Resolution::Found {
+                path: exe.to_path_buf(),
+                architecture: Ok(arch),
+                loadable: true,
+                repeated: false,
+            },
+            error: None,
+            children: vec![],
+        };
+        expanded.insert(normalize(exe));
+        self.expand(&mut root, exe, app_dir, arch, &mut expanded);
+        Ok(root)
+    }
+
+    fn expand(
+        &self,
+        node: &mut Dependency,
+        path: &Path,
+        app_dir: &Path,
+        arch: Architecture,
+        expanded: &mut HashSet<String>,
+    ) {
+        let imports = match detect::imports::read_imports_file(path) {
+            Ok(imports) => imports,
+            Err(e) => {
+                node.error = Some(e);
+                return;
+            }
+        };
+        for import in imports {
+            let mut child = Dependency {
+                name: import.dll.clone(),
+                delay_load: import.delay_load,
+                resolution: Resolution::Missing,
+                error: None,
+                children: vec![],
+            };
+            if is_api_set(&import.dll) {
+                child.resolution = Resolution::ApiSet;
+            } else if let Some(dll_path) = self.resolve(&import.dll, app_dir, arch) {
+                let architecture =
+                    detect::pe::detect_executable_architecture_file_for_host(&dll_path, self.host);
+                let loadable = matches!(architecture, Ok(dll_arch) if self.is_loadable(arch, &dll_path, dll_arch));
+                let repeated = !expanded.insert(normalize(&dll_path));
+                if !repeated && architecture.is_ok() {
+                    self.expand(&mut child, &dll_path, app_dir, arch, expanded);
+                }
+                child.resolution = Resolution::Found {
+                    path: dll_path,
+                    architecture,
+                    loadable,
+                    repeated,
+                };
+            }
+            node.children.push(child);
+        }
+    }
+}
+
+/// Map an absolute path of the running system onto `root`, e.g. `C:\Tools` onto `D:\mnt\` is
+/// `D:\mnt\Tools`.
+pub fn rebase(path: &Path, root: &Path) -> PathBuf {
+    root.join(
+        path.components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect::<PathBuf>(),
+    )
+}
+
+fn is_api_set(dll: &str) -> bool {
+    let dll = dll.to_ascii_lowercase();
+    dll.starts_with("api-ms-") || dll.starts_with("ext-ms-")
+}
+
+/// File names on Windows are case insensitive, so are the keys of the expanded set.
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const PE_X64: &[u8] = include_bytes!("../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+    const PE_ARM64: &[u8] = include_bytes!("../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+    const PE_ARM64EC: &[u8] = include_bytes!("../test_assets/testbin_arm64ec-pc-windows-msvc.exe");
+
+    #[test]
+    fn test_system_dir() {
+        let root = PathBuf::from("root");
+        let resolver = Resolver::new(root.to_path_buf(), Architecture::Arm64, vec![]);
+        let windows = root.join("Windows");
+        assert_eq!(
+            resolver.system_dir(Architecture::I386),
+            windows.join("SysWOW64")
+        );
+        assert_eq!(
+            resolver.system_dir(Architecture::ArmNt),
+            windows.join("SysArm32")
+        );
+        assert_eq!(
+            resolver.system_dir(Architecture::Amd64),
+            windows.join("System32")
+        );
+        let resolver = Resolver::new(root, Architecture::I386, vec![]);
+        assert_eq!(
+            resolver.system_dir(Architecture::I386),
+            windows.join("System32")
+        );
+    }
+
+    #[test]
+    fn test_analyze() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let system32 = root.join("Windows").join("System32");
+        let app = root.join("app");
+        fs::create_dir_all(&system32).unwrap();
+        fs::create_dir_all(&app).unwrap();
+        fs::write(system32.join("kernel32.dll"), PE_ARM64EC).unwrap();
+        fs::write(app.join("app.exe"), PE_X64).unwrap();
+
+        let resolver = Resolver::new(root.to_path_buf(), Architecture::Arm64, vec![]);
+        let tree = resolver.analyze(&app.join("app.exe")).unwrap();
+        assert!(!tree.has_problem());
+        assert_eq!(tree.children.len(), 1);
+        assert!(matches!(
+            &tree.children[0].resolution,
+            Resolution::Found { path, loadable: true, .. } if *path == system32.join("kernel32.dll")
+        ));
+
+        // a plain ARM64 DLL, unlike an ARM64X one, does not load into x64 processes
+        fs::write(system32.join("kernel32.dll"), PE_ARM64).unwrap();
+        let tree = resolver.analyze(&app.join("app.exe")).unwrap();
+        assert!(tree.has_problem());
+
+        // an ARM64 process cannot load an x64 kernel32 from the application directory
+        fs::write(app.join("app.exe"), PE_ARM64).unwrap();
+        fs::write(app.join("kernel32.dll"), PE_X64).unwrap();
+        let tree = resolver.analyze(&app.join("app.exe")).unwrap();
+        assert!(tree.has_problem());
+        assert!(matches!(
+            &tree.children[0].resolution,
+            Resolution::Found { path, loadable: false, .. } if *path == app.join("kernel32.dll")
+        ));
+
+        fs::remove_dir_all(&system32).unwrap();
+        fs::remove_file(app.join("kernel32.dll")).unwrap();
+        let tree = resolver.analyze(&app.join("app.exe")).unwrap();
+        assert!(matches!(tree.children[0].resolution, Resolution::Missing));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let root = PathBuf::from("mnt");
+        assert_eq!(
+            rebase(Path::new("/Program Files/Tool"), &root),
+            root.join("Program Files").join("Tool")
+        );
+    }
+}
@@ -1,11 +1,14 @@
 use std::sync::LazyLock;
 
-use snafu::{OptionExt, ResultExt};
-use windows::Win32::{
-    Foundation::HANDLE,
-    System::{
-        SystemInformation::IMAGE_FILE_MACHINE,
-        Threading::{GetCurrentProcess, IsWow64Process2},
+use snafu::ResultExt;
+use windows::{
+    Wdk::System::SystemServices::RtlGetVersion,
+    Win32::{
+        Foundation::HANDLE,
+        System::{
+            SystemInformation::{IMAGE_FILE_MACHINE, OSVERSIONINFOW},
+            Threading::{GetCurrentProcess, IsWow64Process2},
+        },
     },
 };
 
@@ -28,12 +31,7 @@ static CURRENT_ARCHITECTURE_CACHE: LazyLock<Architecture> = LazyLock::new(|| {
                 call: "IsWow64Process2",
             })?;
         }
-        native_machine
-            .try_into()
-            .ok()
-            .context(InvalidImageFileMachineSnafu {
-                machine: native_machine.0,
-            })
+        Ok(native_machine.into())
     }()
     .expect("Failed to get current system architecture")
 });
@@ -42,6 +40,28 @@ pub fn get_current_sys_architecture() -> Architecture {
     *CURRENT_ARCHITECTURE_CACHE
 }
 
+static CURRENT_OS_BUILD_CACHE: LazyLock<u32> = LazyLock::new(|| {
+    || -> Result<u32> {
+        // unlike GetVersionEx, RtlGetVersion does not lie to unmanifested applications
+        let mut info = OSVERSIONINFOW {
+            dwOSVersionInfoSize: std::mem::size_of::<OSVERSIONINFOW>() as u32,
+            ..Default::default()
+        };
+        unsafe { RtlGetVersion(&mut info) }
+            .ok()
+            .context(WindowsDetailedSnafu {
+                op: "get current os build",
+                call: "RtlGetVersion",
+            })?;
+        Ok(info.dwBuildNumber)
+    }()
+    .expect("Failed to get current os build")
+});
+
+pub fn get_current_os_build() -> u32 {
+    *CURRENT_OS_BUILD_CACHE
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -51,4 +71,11 @@ mod test {
         let arch = get_current_sys_architecture();
         println!("Current system architecture: {arch:?}");
     }
+
+    #[test]
+    fn test_get_current_os_build() {
+        let build = get_current_os_build();
+        println!("Current os build: {build}");
+        assert!(build > 0);
+    }
 }
@@ -5,8 +5,10 @@ use snafu::Snafu;
 pub enum Error {
     #[snafu(display("object parsing error: {}", source))]
     Object { source: object::Error },
+    #[cfg(windows)]
     #[snafu(display("windows api error: {}", source))]
     Windows { source: windows::core::Error },
+    #[cfg(windows)]
     #[snafu(display("when {}, calls to windows api {} failed: {}", op, call, source))]
     WindowsDetailed {
         source: windows::core::Error,
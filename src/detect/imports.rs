@@ -0,0 +1,127 @@
+//! Read the DLLs an executable imports, from both its import and delay-import tables.
+
+use std::io::{Read, Seek};
+
+use object::{
+    LittleEndian, ReadCache, ReadRef,
+    pe::{IMAGE_NT_OPTIONAL_HDR32_MAGIC, IMAGE_NT_OPTIONAL_HDR64_MAGIC},
+    read::pe::{ImageNtHeaders, PeFile},
+};
+use snafu::ResultExt;
+
+use super::error::*;
+
+/// A DLL imported by an executable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// The DLL name as written in the import table, e.g. `KERNEL32.dll`.
+    pub dll: String,
+    /// Whether the DLL is delay-loaded, i.e. only loaded on the first call into it.
+    pub delay_load: bool,
+}
+
+pub fn read_imports<R>(bytes: R) -> Result<Vec<Import>>
+where
+    R: Read + Seek,
+{
+    // the import tables may be anywhere in the file, let object seek around and only cache what it reads
+    let cache = ReadCache::new(bytes);
+    match object::read::pe::optional_header_magic(&cache).context(ObjectSnafu)? {
+        IMAGE_NT_OPTIONAL_HDR32_MAGIC => read_imports_of(
+            &PeFile::<object::pe::ImageNtHeaders32, _>::parse(&cache).context(ObjectSnafu)?,
+        ),
+        IMAGE_NT_OPTIONAL_HDR64_MAGIC => read_imports_of(
+            &PeFile::<object::pe::ImageNtHeaders64, _>::parse(&cache).context(ObjectSnafu)?,
+        ),
+        magic => EmptySnafu {
+            msg: format!("unknown optional header magic {magic:#x}"),
+        }
+        .fail(),
+    }
+}
+
+pub fn read_imports_file<P>(path: P) -> Result<Vec<Import>>
+where
+    P: AsRef<std::path::Path>,
+{
+    let file = std::fs::File::open(path)?;
+    read_imports(file)
+}
+
+pub(super) fn read_imports_of<'data, Pe, R>(file: &PeFile<'data, Pe, R>) -> Result<Vec<Import>>
+where
+    Pe: ImageNtHeaders,
+    R: ReadRef<'data>,
+{
+    let mut imports = Vec::new();
+
+    if let Some(table) = file.import_table().context(ObjectSnafu)? {
+        let mut descriptors = table.descriptors().context(ObjectSnafu)?;
+        while let Some(descriptor) = descriptors.next().context(ObjectSnafu)? {
+            let name = table
+                .name(descriptor.name.get(LittleEndian))
+                .context(ObjectSnafu)?;
+            imports.push(Import {
+                dll: String::from_utf8_lossy(name).into_owned(),
+                delay_load: false,
+            });
+        }
+    }
+
+    if let Some(table) = file
+        .data_directories()
+        .delay_load_import_table(file.data(), &file.section_table())
+        .context(ObjectSnafu)?
+    {
+        let mut descriptors = table.descriptors().context(ObjectSnafu)?;
+        while let Some(descriptor) = descriptors.next().context(ObjectSnafu)? {
+            let name = table
+                .name(descriptor.dll_name_rva.get(LittleEndian))
+                .context(ObjectSnafu)?;
+            imports.push(Import {
+                dll: String::from_utf8_lossy(name).into_owned(),
+                delay_load: true,
+            });
+        }
+    }
+
+    Ok(imports)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PE_X86: &[u8] = include_bytes!("../../test_assets/testbin_i686-pc-windows-msvc.exe");
+    const PE_X64: &[u8] = include_bytes!("../../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+    const PE_ARM64: &[u8] = include_bytes!("../../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+    const PE_DOTNET: &[u8] = include_bytes!("../../test_assets/mscorlib.dll");
+
+    #[test]
+    fn test_read_imports() {
+        // the test binaries only call into kernel32
+        for bin in [PE_X86, PE_X64, PE_ARM64] {
+            let imports = read_imports(std::io::Cursor::new(bin)).expect("Failed to read imports");
+            assert!(
+                imports
+                    .iter()
+                    .any(|i| i.dll.eq_ignore_ascii_case("kernel32.dll") && !i.delay_load),
+                "kernel32.dll not imported: {imports:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_imports_dotnet() {
+        let imports =
+            read_imports(std::io::Cursor::new(PE_DOTNET)).expect("Failed to read imports");
+        // IL-only assemblies import nothing but the runtime's entry point
+        assert_eq!(
+            imports,
+            [Import {
+                dll: "mscoree.dll".to_string(),
+                delay_load: false,
+            }]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 mod error;
-pub use error::Error;
+pub use error::{Error, Result};
+#[cfg(windows)]
 pub mod current;
+pub mod imports;
 pub mod pe;
 pub mod process;
@@ -7,19 +7,30 @@ use std::{
 
 use super::error::*;
 use object::{
-    LittleEndian, ReadRef,
+    LittleEndian, ReadCache, ReadRef,
     coff::CoffHeader,
     pe::{
-        COMIMAGE_FLAGS_32BITREQUIRED, COMIMAGE_FLAGS_ILONLY, ImageCor20Header,
-        ImageOptionalHeader32, ImageSectionHeader,
+        COMIMAGE_FLAGS_32BITREQUIRED, COMIMAGE_FLAGS_ILONLY, IMAGE_DIRECTORY_ENTRY_IMPORT,
+        IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG, IMAGE_NT_OPTIONAL_HDR32_MAGIC,
+        IMAGE_NT_OPTIONAL_HDR64_MAGIC, IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_EXECUTE,
+        IMAGE_SUBSYSTEM_NATIVE, ImageCor20Header, ImageLoadConfigDirectory32,
+        ImageLoadConfigDirectory64, ImageOptionalHeader32, ImageSectionHeader,
     },
-    read::pe::{DataDirectories, ImageOptionalHeader},
+    read::pe::{DataDirectories, ImageNtHeaders, ImageOptionalHeader, PeFile, PeFile32, PeFile64},
 };
 use snafu::{OptionExt, ResultExt};
 
 use crate::architecture::Architecture;
 
-pub fn detect_executable_architecture<R>(mut bytes: R) -> Result<Architecture>
+/// Bump whenever the results of detection change for some image, to invalidate cached results.
+pub const DETECTOR_VERSION: u32 = 1;
+
+/// The architecture of an executable, where AnyCPU .NET assemblies, which run as whatever the
+/// host natively is, are reported as `host`.
+pub fn detect_executable_architecture_for_host<R>(
+    mut bytes: R,
+    host: Architecture,
+) -> Result<Architecture>
 where
     R: Read + Seek,
 {
@@ -110,24 +121,192 @@ where
                     }
                     // Any CPU
                     else if flags & COMIMAGE_FLAGS_ILONLY != 0 {
-                        return Ok(Architecture::current());
+                        return Ok(host);
                     }
                 }
             }
         };
     }
-    machine
-        .try_into()
+    Ok(machine.into())
+}
+
+pub fn detect_executable_architecture_file_for_host<P>(
+    path: P,
+    host: Architecture,
+) -> Result<Architecture>
+where
+    P: AsRef<std::path::Path>,
+{
+    let file = std::fs::File::open(path)?;
+    detect_executable_architecture_for_host(file, host)
+}
+
+/// What an image is, beyond the machine type in its header.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    /// An image with code, which only loads into processes of a compatible architecture.
+    Code,
+    /// An image without executable sections, entry point or imports, such as MUI files and icon
+    /// libraries. These are only ever mapped as data, so they load into any process regardless
+    /// of their machine type.
+    ResourceOnly,
+    /// A kernel-mode driver. Drivers run in the kernel, which cannot emulate foreign code, so a
+    /// driver never loads on a host of a different architecture.
+    Driver,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ImageInfo {
+    pub architecture: Architecture,
+    pub kind: ImageKind,
+}
+
+impl ImageInfo {
+    /// Whether the image loads into processes of any architecture.
+    pub fn is_architecture_neutral(&self) -> bool {
+        self.kind == ImageKind::ResourceOnly
+    }
+}
+
+pub fn detect_image_info<R>(mut bytes: R, host: Architecture) -> Result<ImageInfo>
+where
+    R: Read + Seek,
+{
+    let architecture = detect_executable_architecture_for_host(&mut bytes, host)?;
+    bytes.seek(SeekFrom::Start(0))?;
+    let cache = ReadCache::new(bytes);
+    let kind = match object::read::pe::optional_header_magic(&cache).context(ObjectSnafu)? {
+        IMAGE_NT_OPTIONAL_HDR32_MAGIC => detect_image_kind(
+            &PeFile::<object::pe::ImageNtHeaders32, _>::parse(&cache).context(ObjectSnafu)?,
+        )?,
+        IMAGE_NT_OPTIONAL_HDR64_MAGIC => {
+            detect_image_kind(&PeFile64::parse(&cache).context(ObjectSnafu)?)?
+        }
+        magic => EmptySnafu {
+            msg: format!("unknown optional header magic {magic:#x}"),
+        }
+        .fail()?,
+    };
+    Ok(ImageInfo { architecture, kind })
+}
+
+pub fn detect_image_info_file<P>(path: P, host: Architecture) -> Result<ImageInfo>
+where
+    P: AsRef<std::path::Path>,
+{
+    let file = std::fs::File::open(path)?;
+    detect_image_info(file, host)
+}
+
+fn detect_image_kind<'data, Pe, R>(file: &PeFile<'data, Pe, R>) -> Result<ImageKind>
+where
+    Pe: ImageNtHeaders,
+    R: ReadRef<'data>,
+{
+    let optional_header = file.nt_headers().optional_header();
+    // native subsystem images that are not drivers do exist, e.g. smss.exe and autochk.exe,
+    // but those link against ntdll rather than the kernel
+    if optional_header.subsystem() == IMAGE_SUBSYSTEM_NATIVE {
+        match super::imports::read_imports_of(file) {
+            Ok(imports)
+                if imports.iter().any(|import| {
+                    let dll = import.dll.to_ascii_lowercase();
+                    dll == "ntoskrnl.exe" || dll == "hal.dll" || dll.ends_with(".sys")
+                }) =>
+            {
+                return Ok(ImageKind::Driver);
+            }
+            Ok(_) => {}
+            // the architecture is known all the same, do not fail the whole detection on it
+            Err(_) => return Ok(ImageKind::Code),
+        }
+    }
+
+    let has_entry_point = optional_header.address_of_entry_point() != 0;
+    let has_imports = file
+        .data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT)
+        .is_some_and(|dir| dir.virtual_address.get(LittleEndian) != 0);
+    let has_code = file.section_table().iter().any(|section| {
+        section.characteristics.get(LittleEndian) & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE)
+            != 0
+    });
+    Ok(if has_entry_point || has_imports || has_code {
+        ImageKind::Code
+    } else {
+        ImageKind::ResourceOnly
+    })
+}
+
+/// Whether the image carries CHPE (hybrid) metadata in its load configuration.
+///
+/// Hybrid images are ARM64EC images, which carry the x64 machine type, ARM64X images, which
+/// carry the ARM64 machine type but also contain ARM64EC code to serve x64 processes, and x86
+/// CHPE images, which carry the x86 machine type but contain ARM64 code to run natively in
+/// emulated x86 processes.
+pub fn is_hybrid_executable<R>(bytes: R) -> Result<bool>
+where
+    R: Read + Seek,
+{
+    let cache = ReadCache::new(bytes);
+    match object::read::pe::optional_header_magic(&cache).context(ObjectSnafu)? {
+        IMAGE_NT_OPTIONAL_HDR64_MAGIC => has_chpe_metadata(
+            &PeFile64::parse(&cache).context(ObjectSnafu)?,
+            mem::offset_of!(ImageLoadConfigDirectory64, chpe_metadata_pointer)
+                ..mem::offset_of!(ImageLoadConfigDirectory64, chpe_metadata_pointer) + 8,
+        ),
+        IMAGE_NT_OPTIONAL_HDR32_MAGIC => has_chpe_metadata(
+            &PeFile32::parse(&cache).context(ObjectSnafu)?,
+            mem::offset_of!(ImageLoadConfigDirectory32, chpe_metadata_pointer)
+                ..mem::offset_of!(ImageLoadConfigDirectory32, chpe_metadata_pointer) + 4,
+        ),
+        _ => Ok(false),
+    }
+}
+
+/// Whether the CHPE metadata pointer, at `field` in the load configuration, is set.
+fn has_chpe_metadata<'data, Pe, R>(
+    file: &PeFile<'data, Pe, R>,
+    field: std::ops::Range<usize>,
+) -> Result<bool>
+where
+    Pe: ImageNtHeaders,
+    R: ReadRef<'data>,
+{
+    let Some(directory) = file.data_directory(IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG) else {
+        return Ok(false);
+    };
+    let Some((offset, available)) = file
+        .section_table()
+        .pe_file_range_at(directory.virtual_address.get(LittleEndian))
+    else {
+        return Ok(false);
+    };
+    // the load config grows with every release, only read as far as this image has it
+    let len = available.min(directory.size.get(LittleEndian));
+    let load_config = file
+        .data()
+        .read_bytes_at(offset.into(), len.into())
         .ok()
-        .context(InvalidImageFileMachineSnafu { machine })
+        .context(EmptySnafu {
+            msg: "read IMAGE_LOAD_CONFIG_DIRECTORY",
+        })?;
+    let Some(size) = load_config.get(..4) else {
+        return Ok(false);
+    };
+    let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+    match load_config.get(field.clone()) {
+        Some(pointer) if size >= field.end => Ok(pointer.iter().any(|&b| b != 0)),
+        _ => Ok(false),
+    }
 }
 
-pub fn detect_executable_architecture_file<P>(path: P) -> Result<Architecture>
+pub fn is_hybrid_executable_file<P>(path: P) -> Result<bool>
 where
     P: AsRef<std::path::Path>,
 {
     let file = std::fs::File::open(path)?;
-    detect_executable_architecture(file)
+    is_hybrid_executable(file)
 }
 
 #[cfg(test)]
@@ -151,21 +330,189 @@ mod test {
             Architecture::Arm64,
             Architecture::Amd64,
         ];
-        for (bin, expected_arch) in bins.into_iter().zip(expected_architectures.into_iter()) {
-            let arch = detect_executable_architecture(std::io::Cursor::new(bin))
-                .expect("Failed to detect architecture");
+        for (bin, expected_arch) in bins.into_iter().zip(expected_architectures) {
+            let arch = detect_executable_architecture_for_host(
+                std::io::Cursor::new(bin),
+                Architecture::Arm64,
+            )
+            .expect("Failed to detect architecture");
             assert_eq!(arch, expected_arch, "Architecture mismatch for binary");
         }
     }
 
     #[test]
-    fn test_detect_executable_architecture_dotnet() {
-        let arch = detect_executable_architecture(std::io::Cursor::new(PE_DOTNET))
-            .expect("Failed to detect architecture for .NET assembly");
+    fn test_is_hybrid_executable() {
+        for (bin, expected) in [
+            (PE_X86.to_vec(), false),
+            (PE_X64.to_vec(), false),
+            (PE_ARM64.to_vec(), false),
+            (PE_ARM64EC.to_vec(), true),
+            (PE_DOTNET.to_vec(), false),
+            (with_chpe_metadata_x86(PE_X86, 0x1000), true),
+            (with_chpe_metadata_x86(PE_X86, 0), false),
+        ] {
+            let hybrid = is_hybrid_executable(std::io::Cursor::new(bin))
+                .expect("Failed to read load config");
+            assert_eq!(hybrid, expected);
+        }
+    }
+
+    /// Give a PE32 executable a load configuration, over the start of its first data section,
+    /// with `chpe_metadata_pointer` as an x86 CHPE image has it.
+    fn with_chpe_metadata_x86(bin: &[u8], chpe_metadata_pointer: u32) -> Vec<u8> {
+        let mut bin = bin.to_vec();
+        let u16_at = |bin: &[u8], at: usize| u16::from_le_bytes([bin[at], bin[at + 1]]) as usize;
+        let u32_at =
+            |bin: &[u8], at: usize| u32::from_le_bytes(bin[at..at + 4].try_into().unwrap());
+        let nt = u32_at(&bin, 0x3c) as usize;
+        let file_header = nt + 4;
+        let optional_header = file_header + 20;
         assert_eq!(
-            arch,
-            Architecture::current(),
-            ".NET assembly are always considered the same as the current architecture"
+            u16_at(&bin, optional_header) as u16,
+            IMAGE_NT_OPTIONAL_HDR32_MAGIC
         );
+        let size = mem::size_of::<ImageLoadConfigDirectory32>();
+        let sections = optional_header + u16_at(&bin, file_header + 16);
+        let section = (0..u16_at(&bin, file_header + 2))
+            .map(|i| sections + i * mem::size_of::<ImageSectionHeader>())
+            .find(|&section| {
+                u32_at(&bin, section + 36) & IMAGE_SCN_CNT_CODE == 0
+                    && u32_at(&bin, section + 8).min(u32_at(&bin, section + 16)) as usize >= size
+            })
+            .unwrap();
+        let virtual_address = u32_at(&bin, section + 12);
+        let load_config = u32_at(&bin, section + 20) as usize;
+        bin[load_config..load_config + size].fill(0);
+        bin[load_config..load_config + 4].copy_from_slice(&(size as u32).to_le_bytes());
+        let pointer =
+            load_config + mem::offset_of!(ImageLoadConfigDirectory32, chpe_metadata_pointer);
+        bin[pointer..pointer + 4].copy_from_slice(&chpe_metadata_pointer.to_le_bytes());
+        let directory = optional_header + 96 + IMAGE_DIRECTORY_ENTRY_LOAD_CONFIG * 8;
+        bin[directory..directory + 4].copy_from_slice(&virtual_address.to_le_bytes());
+        bin[directory + 4..directory + 8].copy_from_slice(&(size as u32).to_le_bytes());
+        bin
+    }
+
+    /// Turn an executable into what looks like a resource-only image, by dropping its entry
+    /// point, its imports and the code flags of its sections.
+    fn strip_code(bin: &[u8]) -> Vec<u8> {
+        let mut bin = bin.to_vec();
+        let u16_at = |bin: &[u8], at: usize| u16::from_le_bytes([bin[at], bin[at + 1]]) as usize;
+        let nt = u32::from_le_bytes(bin[0x3c..0x40].try_into().unwrap()) as usize;
+        let file_header = nt + 4;
+        let number_of_sections = u16_at(&bin, file_header + 2);
+        let optional_header = file_header + 20;
+        let data_directories = optional_header
+            + match u16_at(&bin, optional_header) as u16 {
+                IMAGE_NT_OPTIONAL_HDR32_MAGIC => 96,
+                _ => 112,
+            };
+        // AddressOfEntryPoint
+        bin[optional_header + 16..optional_header + 20].fill(0);
+        let import = data_directories + IMAGE_DIRECTORY_ENTRY_IMPORT * 8;
+        bin[import..import + 8].fill(0);
+        let sections = optional_header + u16_at(&bin, file_header + 16);
+        for i in 0..number_of_sections {
+            let characteristics = sections + i * mem::size_of::<ImageSectionHeader>() + 36;
+            let flags = u32::from_le_bytes(
+                bin[characteristics..characteristics + 4]
+                    .try_into()
+                    .unwrap(),
+            ) & !(IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE);
+            bin[characteristics..characteristics + 4].copy_from_slice(&flags.to_le_bytes());
+        }
+        bin
+    }
+
+    /// Turn an executable into what looks like a driver, by switching it to the native
+    /// subsystem and importing from the kernel instead of kernel32.
+    fn make_driver(bin: &[u8], import_kernel: bool) -> Vec<u8> {
+        let mut bin = bin.to_vec();
+        let nt = u32::from_le_bytes(bin[0x3c..0x40].try_into().unwrap()) as usize;
+        // Subsystem, at the same offset in both PE32 and PE32+ optional headers
+        let subsystem = nt + 4 + 20 + 68;
+        bin[subsystem..subsystem + 2].copy_from_slice(&IMAGE_SUBSYSTEM_NATIVE.to_le_bytes());
+        if import_kernel {
+            let at = bin
+                .windows(12)
+                .position(|w| w.eq_ignore_ascii_case(b"kernel32.dll"))
+                .unwrap();
+            bin[at..at + 12].copy_from_slice(b"ntoskrnl.exe");
+        }
+        bin
+    }
+
+    /// Point the import directory of an executable outside of the image.
+    fn break_imports(bin: &[u8]) -> Vec<u8> {
+        let mut bin = bin.to_vec();
+        let nt = u32::from_le_bytes(bin[0x3c..0x40].try_into().unwrap()) as usize;
+        let optional_header = nt + 4 + 20;
+        let data_directories = optional_header
+            + match u16::from_le_bytes([bin[optional_header], bin[optional_header + 1]]) {
+                IMAGE_NT_OPTIONAL_HDR32_MAGIC => 96,
+                _ => 112,
+            };
+        let import = data_directories + IMAGE_DIRECTORY_ENTRY_IMPORT * 8;
+        bin[import..import + 4].copy_from_slice(&0x7fff_0000u32.to_le_bytes());
+        bin
+    }
+
+    #[test]
+    fn test_detect_image_info_driver() {
+        for bin in [PE_X86, PE_X64, PE_ARM64] {
+            let info = detect_image_info(
+                std::io::Cursor::new(make_driver(bin, true)),
+                Architecture::Arm64,
+            )
+            .expect("Failed to detect image");
+            assert_eq!(info.kind, ImageKind::Driver);
+            // unreadable imports leave it an image of code of the right architecture
+            let info = detect_image_info(
+                std::io::Cursor::new(break_imports(&make_driver(bin, true))),
+                Architecture::Arm64,
+            )
+            .expect("Failed to detect image");
+            assert_eq!(info.kind, ImageKind::Code);
+            let info = detect_image_info(
+                std::io::Cursor::new(make_driver(bin, false)),
+                Architecture::Arm64,
+            )
+            .expect("Failed to detect image");
+            assert_eq!(info.kind, ImageKind::Code);
+        }
+    }
+
+    #[test]
+    fn test_detect_image_info() {
+        for (bin, expected_arch) in [
+            (PE_X86, Architecture::I386),
+            (PE_X64, Architecture::Amd64),
+            (PE_ARM64, Architecture::Arm64),
+        ] {
+            let info = detect_image_info(std::io::Cursor::new(bin), Architecture::Arm64)
+                .expect("Failed to detect image");
+            assert_eq!(info.architecture, expected_arch);
+            assert_eq!(info.kind, ImageKind::Code);
+
+            let info =
+                detect_image_info(std::io::Cursor::new(strip_code(bin)), Architecture::Arm64)
+                    .expect("Failed to detect image");
+            assert_eq!(info.architecture, expected_arch);
+            assert_eq!(info.kind, ImageKind::ResourceOnly);
+            assert!(info.is_architecture_neutral());
+        }
+        let info = detect_image_info(std::io::Cursor::new(PE_DOTNET), Architecture::Arm64)
+            .expect("Failed to detect image");
+        assert_eq!(info.kind, ImageKind::Code);
+    }
+
+    #[test]
+    fn test_detect_executable_architecture_dotnet() {
+        for host in [Architecture::I386, Architecture::Amd64, Architecture::Arm64] {
+            let arch =
+                detect_executable_architecture_for_host(std::io::Cursor::new(PE_DOTNET), host)
+                    .expect("Failed to detect architecture for .NET assembly");
+            assert_eq!(arch, host);
+        }
     }
 }
@@ -1,76 +1,194 @@
+//! Detect the architecture a running process runs as.
+//!
+//! `IsWow64Process2` only reports a process machine for WOW64 processes. x64 and ARM64EC
+//! processes on ARM64 are not WOW64, so it reports none for them and they pass for native.
+//! `GetProcessInformation(ProcessMachineTypeInfo)`, from Windows 11 on, tells x64 apart from
+//! ARM64, but ARM64EC images carry the x64 machine type, so only the image on disk tells
+//! ARM64EC apart from x64. [`decide`] weighs these, apart from the calls, so that it can be
+//! tested anywhere.
+#![cfg_attr(not(windows), allow(dead_code))]
+
+#[cfg(windows)]
 use super::error::*;
-use snafu::{OptionExt, ResultExt};
-use windows::Win32::{
-    Foundation::{CloseHandle, HANDLE},
-    System::{
+use crate::architecture::Architecture;
+
+/// What the system reports of a process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Machines {
+    /// The machine of a WOW64 process, `None` for other processes.
+    pub wow64: Option<Architecture>,
+    /// The machine of the system.
+    pub native: Architecture,
+    /// The machine from `ProcessMachineTypeInfo`, `None` where it is not available.
+    pub process: Option<Architecture>,
+}
+
+/// The image of a process as found on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MainImage {
+    pub architecture: Architecture,
+    /// Whether it carries CHPE metadata, see [`super::pe::is_hybrid_executable`].
+    pub hybrid: bool,
+}
+
+/// The architecture a process runs as, from what the system reports and its image if it could
+/// be read.
+pub fn decide(machines: &Machines, image: Option<&MainImage>) -> Architecture {
+    use Architecture::*;
+
+    if let Some(wow64) = machines.wow64 {
+        return wow64;
+    }
+    let arm64ec = machines.native == Arm64
+        && matches!(machines.process, None | Some(Amd64 | Arm64))
+        && image.is_some_and(|image| image.architecture == Amd64 && image.hybrid);
+    if arm64ec {
+        return Arm64Ec;
+    }
+    machines
+        .process
+        .or(image.map(|image| image.architecture))
+        .unwrap_or(machines.native)
+}
+
+#[cfg(windows)]
+pub fn detect_process_machines(h_process: windows::Win32::Foundation::HANDLE) -> Result<Machines> {
+    use snafu::ResultExt;
+    use windows::Win32::System::{
         SystemInformation::{IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_UNKNOWN},
-        Threading::{IsWow64Process2, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
-    },
-};
-
-use crate::architecture;
-
-pub fn detect_process_architecture(h_process: HANDLE) -> Result<architecture::Architecture> {
-    let image_file_machine = unsafe {
-        let mut process_machine = IMAGE_FILE_MACHINE::default();
-        let mut native_machine = IMAGE_FILE_MACHINE::default();
-        IsWow64Process2(h_process, &mut process_machine, Some(&mut native_machine)).context(
-            WindowsDetailedSnafu {
-                call: "IsWow64Process2".to_string(),
-                op: "detect_process_architecture".to_string(),
-            },
-        )?;
-        if process_machine != IMAGE_FILE_MACHINE_UNKNOWN {
-            process_machine
-        } else if native_machine == IMAGE_FILE_MACHINE_UNKNOWN {
-            InvalidImageFileMachineSnafu {
-                machine: IMAGE_FILE_MACHINE_UNKNOWN.0,
-            }
-            .fail()?
-        } else {
-            native_machine
-        }
+        Threading::{
+            GetProcessInformation, IsWow64Process2, PROCESS_MACHINE_INFORMATION,
+            ProcessMachineTypeInfo,
+        },
     };
-    image_file_machine
-        .0
-        .try_into()
-        .ok()
-        .context(InvalidImageFileMachineSnafu {
-            machine: image_file_machine.0,
-        })
+
+    let mut process_machine = IMAGE_FILE_MACHINE::default();
+    let mut native_machine = IMAGE_FILE_MACHINE::default();
+    unsafe { IsWow64Process2(h_process, &mut process_machine, Some(&mut native_machine)) }
+        .context(WindowsDetailedSnafu {
+            call: "IsWow64Process2".to_string(),
+            op: "detect_process_machines".to_string(),
+        })?;
+    if native_machine == IMAGE_FILE_MACHINE_UNKNOWN {
+        InvalidImageFileMachineSnafu {
+            machine: IMAGE_FILE_MACHINE_UNKNOWN.0,
+        }
+        .fail()?
+    }
+    // not supported before Windows 11
+    let mut information = PROCESS_MACHINE_INFORMATION::default();
+    let process = unsafe {
+        GetProcessInformation(
+            h_process,
+            ProcessMachineTypeInfo,
+            (&mut information as *mut PROCESS_MACHINE_INFORMATION).cast(),
+            std::mem::size_of::<PROCESS_MACHINE_INFORMATION>() as u32,
+        )
+    }
+    .ok()
+    .filter(|_| information.ProcessMachine != IMAGE_FILE_MACHINE_UNKNOWN)
+    .map(|_| information.ProcessMachine.into());
+    Ok(Machines {
+        wow64: (process_machine != IMAGE_FILE_MACHINE_UNKNOWN).then(|| process_machine.into()),
+        native: native_machine.into(),
+        process,
+    })
 }
 
-pub fn detect_executable_architecture_by_pid(pid: u32) -> Result<architecture::Architecture> {
+#[cfg(windows)]
+pub fn detect_process_machines_by_pid(pid: u32) -> Result<Machines> {
+    use snafu::ResultExt;
+    use windows::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+
     let h_process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.context(
         WindowsDetailedSnafu {
             call: "OpenProcess".to_string(),
             op: format!("detect pid {pid}"),
         },
     )?;
-    let arch = detect_process_architecture(h_process)?;
+    let machines = detect_process_machines(h_process);
     unsafe { CloseHandle(h_process) }.context(WindowsDetailedSnafu {
         call: "CloseHandle".to_string(),
         op: "",
     })?;
-    Ok(arch)
+    machines
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use windows::Win32::System::Threading::{GetCurrentProcess, GetCurrentProcessId};
 
     #[test]
-    fn test_detect_process_architecture() {
+    fn test_decide() {
+        use Architecture::*;
+
+        let machines = |wow64, native, process| Machines {
+            wow64,
+            native,
+            process,
+        };
+        let image = |architecture, hybrid| MainImage {
+            architecture,
+            hybrid,
+        };
+        let arm64ec = image(Amd64, true);
+        let x64 = image(Amd64, false);
+        for (machines, image, expected) in [
+            // WOW64 is told as is
+            (machines(Some(I386), Arm64, Some(I386)), None, I386),
+            (
+                machines(Some(I386), Amd64, None),
+                Some(image(I386, false)),
+                I386,
+            ),
+            // native
+            (
+                machines(None, Arm64, Some(Arm64)),
+                Some(image(Arm64, true)),
+                Arm64,
+            ),
+            (machines(None, Amd64, None), None, Amd64),
+            // ARM64EC and x64 on Windows 11 ARM64
+            (machines(None, Arm64, Some(Amd64)), Some(arm64ec), Arm64Ec),
+            (machines(None, Arm64, Some(Arm64)), Some(arm64ec), Arm64Ec),
+            (machines(None, Arm64, Some(Amd64)), Some(x64), Amd64),
+            (machines(None, Arm64, Some(Amd64)), None, Amd64),
+            // without `ProcessMachineTypeInfo` only the image tells them from ARM64
+            (machines(None, Arm64, None), Some(arm64ec), Arm64Ec),
+            (machines(None, Arm64, None), Some(x64), Amd64),
+            (machines(None, Arm64, None), None, Arm64),
+            // ARM64EC only exists on ARM64
+            (machines(None, Amd64, Some(Amd64)), Some(arm64ec), Amd64),
+        ] {
+            assert_eq!(
+                decide(&machines, image.as_ref()),
+                expected,
+                "{machines:?} {image:?}"
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_detect_process_machines() {
+        use windows::Win32::System::Threading::GetCurrentProcess;
+
         let h_process = unsafe { GetCurrentProcess() };
-        let arch = detect_process_architecture(h_process).unwrap();
-        println!("Detected architecture: {arch:?}");
+        let machines = detect_process_machines(h_process).unwrap();
+        println!("Detected machines: {machines:?}");
     }
 
+    #[cfg(windows)]
     #[test]
-    fn test_detect_executable_architecture_by_pid() {
+    fn test_detect_process_machines_by_pid() {
+        use windows::Win32::System::Threading::GetCurrentProcessId;
+
         let pid = unsafe { GetCurrentProcessId() };
-        let arch = detect_executable_architecture_by_pid(pid).unwrap();
-        println!("Detected architecture by PID: {arch:?}");
+        let machines = detect_process_machines_by_pid(pid).unwrap();
+        println!("Detected machines by PID: {machines:?}");
+        println!("Decided architecture: {:?}", decide(&machines, None));
     }
 }
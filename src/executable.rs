@@ -1,39 +1,45 @@
-use std::{env, fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use snafu::Snafu;
 
-pub fn enumrate_executables() -> Result<impl Iterator<Item = PathBuf>> {
+use crate::search_path::{self, Source};
+
+/// The executables in the directories of `PATH`, with the `PATH` each was found through.
+///
+/// `registry` reads the machine and user `PATH` from the registry, see
+/// [`search_path::entries`].
+pub fn enumrate_executables(
+    registry: bool,
+) -> Result<impl Iterator<Item = (PathBuf, Source)> + Send> {
     struct Iter {
-        path_dirs: std::vec::IntoIter<PathBuf>,
-        current_dir_entries: Option<std::vec::IntoIter<PathBuf>>,
+        path_dirs: std::vec::IntoIter<search_path::Entry>,
+        current_dir_entries: Option<(std::vec::IntoIter<PathBuf>, Source)>,
     }
 
     impl Iterator for Iter {
-        type Item = PathBuf;
+        type Item = (PathBuf, Source);
 
         fn next(&mut self) -> Option<Self::Item> {
             loop {
-                if let Some(ref mut entries) = self.current_dir_entries {
+                if let Some((ref mut entries, source)) = self.current_dir_entries {
                     if let Some(entry) = entries.next() {
-                        return Some(entry);
+                        return Some((entry, source));
                     }
                     self.current_dir_entries = None;
                 }
 
                 let path_dir = self.path_dirs.next()?;
 
-                if let Ok(entries) = fs::read_dir(&path_dir) {
+                if let Ok(entries) = fs::read_dir(&path_dir.dir) {
                     let executable_files: Vec<PathBuf> = entries
                         .filter_map(|entry| {
                             let entry = entry.ok()?;
                             let path = entry.path();
 
-                            if !path.is_file() {
-                                return None;
-                            }
-
-                            let extension = path.extension()?.to_str()?;
-                            if !matches!(extension.to_lowercase().as_str(), "exe" | "dll") {
+                            if !path.is_file() || !has_executable_extension(&path) {
                                 return None;
                             }
                             Some(path)
@@ -41,30 +47,148 @@ pub fn enumrate_executables() -> Result<impl Iterator<Item = PathBuf>> {
                         .collect();
 
                     if !executable_files.is_empty() {
-                        self.current_dir_entries = Some(executable_files.into_iter());
+                        self.current_dir_entries =
+                            Some((executable_files.into_iter(), path_dir.source));
                     }
                 }
             }
         }
     }
 
-    let path_env = env::var("PATH").map_err(|_| Error::PathNotFound)?;
-    let path_dirs: Vec<PathBuf> = path_env
-        .split(';')
-        .map(|dir| PathBuf::from(dir.trim()))
-        .filter(|dir| dir.exists() && dir.is_dir())
-        .collect();
-
     Ok(Iter {
-        path_dirs: path_dirs.into_iter(),
+        path_dirs: search_path::entries(registry)?.into_iter(),
         current_dir_entries: None,
     })
 }
 
+/// Whether `path` has the extension of a PE file we are interested in.
+pub fn has_executable_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "exe" | "dll"))
+}
+
+/// Whether `path` has the extension of a kernel driver.
+///
+/// Drivers are never on `PATH`, so only the scanners that can come across them look for these.
+pub fn has_driver_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("sys"))
+}
+
+/// The files to inspect for `paths` given by the user.
+///
+/// Files are taken as they are, even without an executable extension or when they do not
+/// exist, so that a mistyped path shows up as an error rather than silently nothing.
+/// Directories are searched for files with an executable or driver extension, including their
+/// subdirectories when `recursive` is set.
+pub fn enumrate_paths(
+    paths: &[PathBuf],
+    recursive: bool,
+) -> impl Iterator<Item = PathBuf> + Send + use<> {
+    let paths = paths.to_vec();
+    paths
+        .into_iter()
+        .flat_map(move |path| -> Box<dyn Iterator<Item = PathBuf> + Send> {
+            if !path.is_dir() {
+                return Box::new(std::iter::once(path));
+            }
+            let files: Box<dyn Iterator<Item = PathBuf> + Send> = if recursive {
+                Box::new(walk_files(&path))
+            } else {
+                let mut files = fs::read_dir(&path)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+                    .map(|entry| entry.path())
+                    .collect::<Vec<_>>();
+                files.sort();
+                Box::new(files.into_iter())
+            };
+            Box::new(
+                files.filter(|file| has_executable_extension(file) || has_driver_extension(file)),
+            )
+        })
+}
+
+/// The SHA-256 of the contents of `path`, as lowercase hex.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// The existing directories listed in the `PATH` environment variable, in order.
+pub fn path_dirs() -> Result<Vec<PathBuf>> {
+    Ok(search_path::entries(false)?
+        .into_iter()
+        .map(|entry| entry.dir)
+        .filter(|dir| dir.is_dir())
+        .collect())
+}
+
+/// Recursively enumrate all regular files beneath `root`.
+///
+/// Like the `PATH` scan, directories that cannot be read are silently skipped.
+pub fn walk_files(root: &Path) -> impl Iterator<Item = PathBuf> + Send + use<> {
+    struct Iter {
+        pending_dirs: Vec<PathBuf>,
+        current_dir_entries: Option<std::vec::IntoIter<PathBuf>>,
+    }
+
+    impl Iterator for Iter {
+        type Item = PathBuf;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                if let Some(ref mut entries) = self.current_dir_entries {
+                    if let Some(entry) = entries.next() {
+                        return Some(entry);
+                    }
+                    self.current_dir_entries = None;
+                }
+
+                let dir = self.pending_dirs.pop()?;
+
+                if let Ok(entries) = fs::read_dir(&dir) {
+                    let mut files = Vec::new();
+                    for entry in entries.filter_map(|entry| entry.ok()) {
+                        let Ok(file_type) = entry.file_type() else {
+                            continue;
+                        };
+                        // do not follow symlinks or junctions, they easily form cycles
+                        if file_type.is_dir() {
+                            self.pending_dirs.push(entry.path());
+                        } else if file_type.is_file() {
+                            files.push(entry.path());
+                        }
+                    }
+                    if !files.is_empty() {
+                        self.current_dir_entries = Some(files.into_iter());
+                    }
+                }
+            }
+        }
+    }
+
+    Iter {
+        pending_dirs: vec![root.to_path_buf()],
+        current_dir_entries: None,
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
-    #[snafu(display("PATH environment variable not found"))]
-    PathNotFound,
+    #[snafu(context(false))]
+    #[snafu(display("when reading PATH, {}", source))]
+    SearchPath { source: search_path::Error },
 
     #[snafu(display("IO error while reading directory: {}", source))]
     Io { source: std::io::Error },
@@ -78,8 +202,9 @@ mod tests {
 
     #[test]
     fn test_enumrate_executables() {
-        let executables = enumrate_executables().unwrap();
-        for exe_path in executables {
+        let executables = enumrate_executables(false).unwrap();
+        for (exe_path, source) in executables {
+            assert_eq!(source, Source::Process);
             println!("{exe_path:?}");
             assert!(
                 exe_path.exists(),
@@ -87,4 +212,51 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_enumrate_paths() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let assets = root.join("test_assets");
+        let exe = assets.join("testbin_x86_64-pc-windows-msvc.exe");
+        let missing = root.join("missing.exe");
+
+        let files = enumrate_paths(&[assets.clone(), missing.clone()], false).collect::<Vec<_>>();
+        assert!(files.contains(&exe));
+        assert!(files.contains(&assets.join("mscorlib.dll")));
+        assert_eq!(files.last(), Some(&missing));
+
+        // explicitly given files are kept whatever their extension
+        let readme = root.join("README.md");
+        assert_eq!(
+            enumrate_paths(std::slice::from_ref(&readme), false).collect::<Vec<_>>(),
+            [readme]
+        );
+
+        // test assets are only found below the crate root when recursing
+        let files = enumrate_paths(&[root.to_path_buf()], false).collect::<Vec<_>>();
+        assert!(!files.contains(&exe));
+        let files = enumrate_paths(&[root.to_path_buf()], true).collect::<Vec<_>>();
+        assert!(files.contains(&exe));
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sha256");
+        fs::write(&path, "abc").unwrap();
+        let hash = sha256_file(&path).unwrap();
+        assert_eq!(
+            hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_walk_files() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let files = walk_files(&root).collect::<Vec<_>>();
+        assert!(files.contains(&root.join("main.rs")));
+        assert!(files.contains(&root.join("detect").join("pe.rs")));
+        assert!(files.iter().all(|f| f.is_file()));
+    }
 }
@@ -0,0 +1,605 @@
+//! Parse driver package INF files and check that the binaries they declare per platform are of
+//! that platform's architecture.
+//!
+//! The platforms a package supports are declared by the decorations of its `[Manufacturer]`
+//! models, e.g. `NTamd64` or `NTarm64.10.0...17763`, and the binaries for each platform are
+//! listed in `[SourceDisksFiles.<arch>]` merged with the undecorated `[SourceDisksFiles]`.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{architecture::Architecture, detect};
+
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+/// A line of a section, `key = value, value, ...` or just a list of values.
+pub struct Entry {
+    pub key: Option<String>,
+    pub values: Vec<String>,
+}
+
+pub struct Inf {
+    pub sections: Vec<Section>,
+}
+
+impl Inf {
+    /// Parse an INF from its raw bytes, which are either UTF-16LE with a byte order mark, or
+    /// UTF-8/ANSI.
+    pub fn parse_bytes(bytes: &[u8]) -> Self {
+        let text = match bytes {
+            [0xff, 0xfe, rest @ ..] => String::from_utf16_lossy(
+                &rest
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>(),
+            ),
+            [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+            _ => String::from_utf8_lossy(bytes).into_owned(),
+        };
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut sections: Vec<Section> = Vec::new();
+        let mut logical_line = String::new();
+        for line in text.lines() {
+            let line = strip_comment(line).trim();
+            // a trailing backslash continues the line
+            if let Some(continued) = line.strip_suffix('\\') {
+                logical_line.push_str(continued);
+                continue;
+            }
+            logical_line.push_str(line);
+            let line = std::mem::take(&mut logical_line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push(Section {
+                    name: name.trim().to_string(),
+                    entries: vec![],
+                });
+                continue;
+            }
+            let Some(section) = sections.last_mut() else {
+                continue;
+            };
+            let (key, values) = match split_key(line) {
+                Some((key, values)) => (Some(unquote(key)), values),
+                None => (None, line),
+            };
+            section.entries.push(Entry {
+                key,
+                values: split_values(values),
+            });
+        }
+
+        let mut inf = Inf { sections };
+        inf.substitute_strings();
+        inf
+    }
+
+    /// Section names are case insensitive.
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// A value of the `[Version]` section, e.g. `Provider` or `Class`.
+    pub fn version(&self, key: &str) -> Option<&str> {
+        self.section("Version")?
+            .entries
+            .iter()
+            .find(|e| {
+                e.key
+                    .as_deref()
+                    .is_some_and(|k| k.eq_ignore_ascii_case(key))
+            })
+            .and_then(|e| e.values.first())
+            .map(String::as_str)
+    }
+
+    /// The platform decorations of the package.
+    ///
+    /// These come from the `[Manufacturer]` models, or, for packages without models such as
+    /// primitive drivers, from the decorated `[DefaultInstall]` sections.
+    pub fn decorations(&self) -> Vec<Decoration> {
+        let mut decorations: Vec<Decoration> = match self.section("Manufacturer") {
+            Some(manufacturer) => manufacturer
+                .entries
+                .iter()
+                .flat_map(|e| e.values.iter().skip(1))
+                .filter_map(|d| Decoration::parse(d))
+                .collect(),
+            None => self
+                .sections
+                .iter()
+                .filter_map(|s| {
+                    let (name, decoration) = s.name.split_once('.')?;
+                    if !name.eq_ignore_ascii_case("DefaultInstall") {
+                        return None;
+                    }
+                    Decoration::parse(decoration)
+                })
+                .collect(),
+        };
+        let mut seen = HashSet::new();
+        decorations.retain(|d| seen.insert(d.raw.to_ascii_lowercase()));
+        decorations
+    }
+
+    /// The entries of the section `name` for `arch`, those of `[name.<arch>]` merged with
+    /// those of the undecorated `[name]`, the decorated taking precedence for the same key.
+    fn platform_entries(&self, name: &str, arch: Architecture) -> Vec<&Entry> {
+        let mut entries = inf_arch_name(arch)
+            .and_then(|arch| self.section(&format!("{name}.{arch}")))
+            .map(|section| section.entries.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let decorated = entries
+            .iter()
+            .filter_map(|e| Some(e.key.as_deref()?.to_ascii_lowercase()))
+            .collect::<HashSet<_>>();
+        if let Some(section) = self.section(name) {
+            entries.extend(section.entries.iter().filter(|e| {
+                e.key
+                    .as_deref()
+                    .is_none_or(|key| !decorated.contains(&key.to_ascii_lowercase()))
+            }));
+        }
+        entries
+    }
+
+    /// The files copied from the package for `arch`, as paths relative to the INF directory.
+    pub fn source_files(&self, arch: Architecture) -> Vec<PathBuf> {
+        let disks = self.platform_entries("SourceDisksNames", arch);
+        self.platform_entries("SourceDisksFiles", arch)
+            .into_iter()
+            .filter_map(|entry| {
+                let name = entry.key.as_ref()?;
+                let disk_id = entry.values.first().map(String::as_str).unwrap_or("");
+                // SourceDisksNames: diskid = description[,[tag-or-cab-file],[unused],[path]]
+                let disk_path = disks
+                    .iter()
+                    .find(|e| e.key.as_deref() == Some(disk_id))
+                    .and_then(|e| e.values.get(3))
+                    .map(String::as_str)
+                    .unwrap_or("");
+                // SourceDisksFiles: filename = diskid[,[subdir][,size]]
+                let subdir = entry.values.get(1).map(String::as_str).unwrap_or("");
+                let mut path = PathBuf::new();
+                for part in [disk_path, subdir] {
+                    path.extend(part.split('\\').filter(|p| !p.is_empty() && *p != "."));
+                }
+                Some(path.join(name))
+            })
+            .collect()
+    }
+
+    /// Replace `%key%` tokens with the values of the `[Strings]` section, `%%` is a literal `%`.
+    fn substitute_strings(&mut self) {
+        let strings = self
+            .section("Strings")
+            .map(|s| {
+                s.entries
+                    .iter()
+                    .filter_map(|e| Some((e.key.clone()?, e.values.join(",").replace("%%", "%"))))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let substitute = |value: &mut String| {
+            if !value.contains('%') {
+                return;
+            }
+            let mut result = String::new();
+            let mut parts = value.split('%');
+            result.push_str(parts.next().unwrap_or(""));
+            while let Some(token) = parts.next() {
+                let Some(rest) = parts.next() else {
+                    // unbalanced, keep it as is
+                    result.push('%');
+                    result.push_str(token);
+                    break;
+                };
+                match strings.iter().find(|(k, _)| k.eq_ignore_ascii_case(token)) {
+                    _ if token.is_empty() => result.push('%'),
+                    Some((_, v)) => result.push_str(v),
+                    None => {
+                        result.push('%');
+                        result.push_str(token);
+                        result.push('%');
+                    }
+                }
+                result.push_str(rest);
+            }
+            *value = result;
+        };
+        for section in &mut self.sections {
+            if section.name.eq_ignore_ascii_case("Strings") {
+                continue;
+            }
+            for entry in &mut section.entries {
+                if let Some(key) = &mut entry.key {
+                    substitute(key);
+                }
+                entry.values.iter_mut().for_each(substitute);
+            }
+        }
+    }
+}
+
+/// A target platform decoration, `NT[arch][.[OSMajor][.[OSMinor][.[ProductType][.[SuiteMask][.[BuildNumber]]]]]]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoration {
+    pub raw: String,
+    /// `None` for decorations not restricted to an architecture, such as `NT.6.1`.
+    pub architecture: Option<Architecture>,
+}
+
+impl Decoration {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        let rest = raw
+            .get(..2)?
+            .eq_ignore_ascii_case("NT")
+            .then(|| &raw[2..])?;
+        let arch = rest.split('.').next().unwrap_or("").to_ascii_lowercase();
+        let architecture = match arch.as_str() {
+            "" => None,
+            "x86" => Some(Architecture::I386),
+            "amd64" => Some(Architecture::Amd64),
+            "arm" => Some(Architecture::ArmNt),
+            "arm64" => Some(Architecture::Arm64),
+            "ia64" => Some(Architecture::Ia64),
+            _ => return None,
+        };
+        Some(Decoration {
+            raw: raw.to_string(),
+            architecture,
+        })
+    }
+}
+
+/// The architecture name used in INF decorations and section suffixes.
+fn inf_arch_name(arch: Architecture) -> Option<&'static str> {
+    match arch {
+        Architecture::I386 => Some("x86"),
+        Architecture::Amd64 => Some("amd64"),
+        Architecture::ArmNt => Some("arm"),
+        Architecture::Arm64 => Some("arm64"),
+        Architecture::Ia64 => Some("ia64"),
+        _ => None,
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split `key = values` at the first `=` outside of quotes.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '=' if !quoted => return Some((&line[..i], &line[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a comma separated list of values, commas inside quotes do not separate.
+fn split_values(values: &str) -> Vec<String> {
+    let mut quoted = false;
+    let mut start = 0;
+    let mut result = Vec::new();
+    for (i, c) in values.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                result.push(unquote(&values[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(unquote(&values[start..]));
+    result
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// A PE file declared for a platform of the package.
+pub struct PackageFile {
+    pub platform: Architecture,
+    /// Path relative to the INF directory, as declared.
+    pub name: PathBuf,
+    /// `None` if the file is not in the package.
+    pub architecture: Option<detect::Result<Architecture>>,
+}
+
+impl PackageFile {
+    /// Whether the file is of another architecture than the platform it is declared for.
+    ///
+    /// ARM64X images carry the ARM64 machine and match ARM64, while ARM64EC images carry the x64
+    /// one and do not, as ARM64 processes cannot load them.
+    pub fn is_mismatched(&self) -> bool {
+        matches!(self.architecture, Some(Ok(arch)) if arch != self.platform)
+    }
+}
+
+pub struct Package {
+    pub inf_path: PathBuf,
+    pub provider: Option<String>,
+    pub decorations: Vec<Decoration>,
+    pub files: Vec<PackageFile>,
+}
+
+impl Package {
+    pub fn supports_arm64(&self) -> bool {
+        self.decorations
+            .iter()
+            .any(|d| d.architecture == Some(Architecture::Arm64))
+    }
+
+    /// Read the INF at `inf_path` and check the binaries it declares, taking Any CPU .NET
+    /// images to run as `host`.
+    ///
+    /// Files are looked up at their declared location relative to the INF, and, as DriverStore
+    /// copies of some packages are flattened, next to the INF.
+    pub fn check(inf_path: &Path, host: Architecture) -> Result<Self> {
+        let bytes = fs::read(inf_path).context(IoSnafu { path: inf_path })?;
+        let inf = Inf::parse_bytes(&bytes);
+        let dir = inf_path.parent().unwrap_or(Path::new(""));
+        let decorations = inf.decorations();
+        // several decorations, e.g. per OS build, may name the same platform
+        let mut seen = HashSet::new();
+        let platforms = decorations
+            .iter()
+            .filter_map(|d| d.architecture)
+            .filter(|arch| seen.insert(*arch))
+            .collect::<Vec<_>>();
+
+        let mut files = Vec::new();
+        for platform in platforms {
+            for name in inf.source_files(platform) {
+                if !crate::executable::has_executable_extension(&name)
+                    && !crate::executable::has_driver_extension(&name)
+                {
+                    continue;
+                }
+                let path = [
+                    dir.join(&name),
+                    dir.join(name.file_name().unwrap_or_default()),
+                ]
+                .into_iter()
+                .find(|p| p.is_file());
+                files.push(PackageFile {
+                    platform,
+                    architecture: path.map(|path| {
+                        detect::pe::detect_executable_architecture_file_for_host(path, host)
+                    }),
+                    name,
+                });
+            }
+        }
+
+        Ok(Package {
+            inf_path: inf_path.to_path_buf(),
+            provider: inf.version("Provider").map(str::to_string),
+            decorations,
+            files,
+        })
+    }
+}
+
+/// The DriverStore of the running system, where every installed driver package is staged.
+pub fn driver_store() -> PathBuf {
+    let system_root = std::env::var_os("SystemRoot")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("C:\\Windows"));
+    system_root
+        .join("System32")
+        .join("DriverStore")
+        .join("FileRepository")
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("IO error while reading {}: {}", path.display(), source))]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INF: &str = r#"
+; a comment
+[Version]
+Signature   = "$WINDOWS NT$"
+Class       = Net
+Provider    = %Vendor%
+
+[Manufacturer]
+%Vendor% = Models, NTamd64.10.0...16299, NTarm64.10.0...16299 ; trailing comment
+%Vendor% = Models, NTAMD64.10.0...16299, NTamd64.10.0...17763
+
+[Models.NTamd64.10.0...16299]
+%Device.Desc% = Install, PCI\VEN_1234&DEV_5678
+
+[SourceDisksNames]
+1 = %Disk%,,,""
+
+[SourceDisksFiles.amd64]
+device.sys = 1,\x64
+
+[SourceDisksFiles.arm64]
+device.sys = 1,\arm64
+helper.dll = 1,\arm64
+bridge.dll = 1,\arm64
+device.bin = 1
+
+[Strings]
+Vendor      = "Contoso, Ltd."
+Device.Desc = "Contoso 100%% Network Adapter"
+Disk        = "Install Disk"
+"#;
+
+    #[test]
+    fn test_parse() {
+        let inf = Inf::parse(INF);
+        assert_eq!(inf.version("provider"), Some("Contoso, Ltd."));
+        assert_eq!(inf.version("Class"), Some("Net"));
+        let models = inf.section("models.ntamd64.10.0...16299").unwrap();
+        assert_eq!(
+            models.entries[0].key.as_deref(),
+            Some("Contoso 100% Network Adapter")
+        );
+        assert_eq!(
+            inf.decorations()
+                .iter()
+                .map(|d| d.architecture)
+                .collect::<Vec<_>>(),
+            vec![
+                Some(Architecture::Amd64),
+                Some(Architecture::Arm64),
+                Some(Architecture::Amd64)
+            ]
+        );
+        assert_eq!(
+            inf.source_files(Architecture::Arm64),
+            vec![
+                Path::new("arm64").join("device.sys"),
+                Path::new("arm64").join("helper.dll"),
+                Path::new("arm64").join("bridge.dll"),
+                PathBuf::from("device.bin"),
+            ]
+        );
+        assert!(inf.source_files(Architecture::I386).is_empty());
+    }
+
+    #[test]
+    fn test_source_files_merged() {
+        let inf = Inf::parse(
+            r#"
+[SourceDisksNames]
+1 = Disk
+[SourceDisksNames.arm64]
+2 = Disk,,,\arm64
+
+[SourceDisksFiles]
+common.dll = 1
+device.sys = 1,\x64
+[SourceDisksFiles.arm64]
+DEVICE.SYS = 2
+"#,
+        );
+        assert_eq!(
+            inf.source_files(Architecture::Arm64),
+            vec![
+                Path::new("arm64").join("DEVICE.SYS"),
+                PathBuf::from("common.dll"),
+            ]
+        );
+        assert_eq!(
+            inf.source_files(Architecture::Amd64),
+            vec![
+                PathBuf::from("common.dll"),
+                Path::new("x64").join("device.sys"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bytes_utf16() {
+        let bytes = [0xff, 0xfe]
+            .into_iter()
+            .chain(INF.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect::<Vec<_>>();
+        let inf = Inf::parse_bytes(&bytes);
+        assert_eq!(inf.version("Provider"), Some("Contoso, Ltd."));
+    }
+
+    #[test]
+    fn test_decoration() {
+        let arch = |raw| Decoration::parse(raw).map(|d| d.architecture);
+        assert_eq!(arch("NTx86"), Some(Some(Architecture::I386)));
+        assert_eq!(
+            arch("ntarm64.10.0...22000"),
+            Some(Some(Architecture::Arm64))
+        );
+        assert_eq!(arch("NT.6.1"), Some(None));
+        assert_eq!(arch("NTmips"), None);
+        assert_eq!(arch("Models"), None);
+    }
+
+    #[test]
+    fn test_check() {
+        const PE_X64: &[u8] = include_bytes!("../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+        const PE_ARM64: &[u8] =
+            include_bytes!("../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+        const PE_ARM64EC: &[u8] =
+            include_bytes!("../test_assets/testbin_arm64ec-pc-windows-msvc.exe");
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("x64")).unwrap();
+        fs::create_dir_all(root.join("arm64")).unwrap();
+        fs::write(root.join("device.inf"), INF).unwrap();
+        fs::write(root.join("x64").join("device.sys"), PE_X64).unwrap();
+        // the vendor copied the x64 driver into the ARM64 folder
+        fs::write(root.join("arm64").join("device.sys"), PE_X64).unwrap();
+        fs::write(root.join("arm64").join("helper.dll"), PE_ARM64).unwrap();
+        // an ARM64EC build is no ARM64 one
+        fs::write(root.join("arm64").join("bridge.dll"), PE_ARM64EC).unwrap();
+
+        let package = Package::check(&root.join("device.inf"), Architecture::Arm64).unwrap();
+
+        assert!(package.supports_arm64());
+        assert_eq!(package.files.len(), 4);
+        let mismatched = package
+            .files
+            .iter()
+            .filter(|f| f.is_mismatched())
+            .map(|f| (f.platform, f.name.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            mismatched,
+            [
+                (Architecture::Arm64, Path::new("arm64").join("device.sys")),
+                (Architecture::Arm64, Path::new("arm64").join("bridge.dll")),
+            ]
+        );
+    }
+}
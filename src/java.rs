@@ -0,0 +1,297 @@
+//! Locate Java runtimes bundled with an application and check that the JNI libraries shipped
+//! alongside them can actually be loaded by the bundled JVM.
+//!
+//! A JVM can only load native libraries of its own architecture, so an x64 runtime bundled into
+//! an otherwise ARM64-ready application drags every JNI library into emulation, and an ARM64
+//! runtime next to x64-only JNI libraries fails with `UnsatisfiedLinkError` at runtime.
+
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{architecture::Architecture, detect, executable};
+
+/// A Java runtime found inside the scanned tree.
+pub struct Jre {
+    /// The runtime home, i.e. the directory containing `release` and `bin\java.exe`.
+    pub home: PathBuf,
+    pub release: Result<Release>,
+    /// The architecture of `bin\java.exe`, which is what the JVM process will run as.
+    pub architecture: detect::Result<Architecture>,
+}
+
+/// What the `release` file of a runtime declares.
+pub struct Release {
+    /// `JAVA_VERSION` as declared in the `release` file.
+    pub java_version: Option<String>,
+    /// `OS_ARCH` as declared in the `release` file.
+    pub os_arch: Option<String>,
+}
+
+/// Where a native library was found.
+pub enum Location {
+    File(PathBuf),
+    /// An entry inside a jar, usually extracted to a temporary directory by the application at runtime.
+    JarEntry {
+        jar: PathBuf,
+        entry: String,
+    },
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::File(path) => write!(f, "{}", path.display()),
+            Location::JarEntry { jar, entry } => write!(f, "{}!/{}", jar.display(), entry),
+        }
+    }
+}
+
+/// A native library that may be loaded through JNI.
+pub struct NativeLibrary {
+    /// A jar that could not be listed is reported as a [`Location::File`] with its error.
+    pub location: Location,
+    pub architecture: Result<Architecture>,
+}
+
+impl NativeLibrary {
+    /// Returns the runtimes in `jres` that cannot load this library.
+    ///
+    /// Runtimes or libraries whose architecture could not be detected are never reported.
+    pub fn incompatible_jres<'a>(&self, jres: &'a [Jre]) -> Vec<&'a Jre> {
+        let Ok(arch) = self.architecture else {
+            return vec![];
+        };
+        jres.iter()
+            .filter(|jre| matches!(jre.architecture, Ok(jre_arch) if jre_arch != arch))
+            .collect()
+    }
+}
+
+pub struct Report {
+    pub jres: Vec<Jre>,
+    pub libraries: Vec<NativeLibrary>,
+}
+
+/// Scan `root` for bundled Java runtimes and the native libraries around them.
+///
+/// Native libraries are `.dll` files outside of any runtime home, plus `.dll` entries inside
+/// the jars of the application, which is where libraries such as JNA or LWJGL keep their
+/// per-platform natives. Any CPU .NET images are taken to run as `host`.
+pub fn scan(root: &Path, host: Architecture) -> Report {
+    let files = executable::walk_files(root).collect::<Vec<_>>();
+
+    let jres = files
+        .iter()
+        .filter(|f| f.file_name().is_some_and(|n| n == "release"))
+        .filter_map(|f| f.parent())
+        .filter(|home| home.join("bin").join("java.exe").is_file())
+        .map(|home| read_jre(home, host))
+        .collect::<Vec<_>>();
+
+    let mut libraries = Vec::new();
+    for file in files
+        .iter()
+        .filter(|f| !jres.iter().any(|jre| f.starts_with(&jre.home)))
+    {
+        match extension_lowercase(file).as_deref() {
+            Some("dll") => libraries.push(NativeLibrary {
+                location: Location::File(file.clone()),
+                architecture: detect::pe::detect_executable_architecture_file_for_host(file, host)
+                    .context(DetectSnafu),
+            }),
+            Some("jar") => scan_jar(file, host, &mut libraries),
+            _ => {}
+        }
+    }
+
+    Report { jres, libraries }
+}
+
+fn read_jre(home: &Path, host: Architecture) -> Jre {
+    Jre {
+        home: home.to_path_buf(),
+        release: read_release(&home.join("release")),
+        architecture: detect::pe::detect_executable_architecture_file_for_host(
+            home.join("bin").join("java.exe"),
+            host,
+        ),
+    }
+}
+
+fn read_release(path: &Path) -> Result<Release> {
+    let release = fs::read_to_string(path).context(IoSnafu { path })?;
+    let property = |key: &str| {
+        release.lines().find_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            (k.trim() == key).then(|| v.trim().trim_matches('"').to_string())
+        })
+    };
+    Ok(Release {
+        java_version: property("JAVA_VERSION"),
+        os_arch: property("OS_ARCH"),
+    })
+}
+
+/// DLL entries larger than this are not read, a JNI library is far smaller.
+const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+fn scan_jar(jar: &Path, host: Architecture, libraries: &mut Vec<NativeLibrary>) {
+    let archive = fs::File::open(jar)
+        .context(IoSnafu { path: jar })
+        .and_then(|file| zip::ZipArchive::new(file).context(ZipSnafu { path: jar }));
+    let mut archive = match archive {
+        Ok(archive) => archive,
+        Err(e) => {
+            libraries.push(NativeLibrary {
+                location: Location::File(jar.to_path_buf()),
+                architecture: Err(e),
+            });
+            return;
+        }
+    };
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i).context(ZipSnafu { path: jar }) {
+            Ok(entry) => entry,
+            Err(e) => {
+                libraries.push(NativeLibrary {
+                    location: Location::File(jar.to_path_buf()),
+                    architecture: Err(e),
+                });
+                continue;
+            }
+        };
+        if !entry.is_file() || !entry.name().to_lowercase().ends_with(".dll") {
+            continue;
+        }
+        let location = Location::JarEntry {
+            jar: jar.to_path_buf(),
+            entry: entry.name().to_string(),
+        };
+        let architecture = read_entry(&mut entry, jar).and_then(|buf| {
+            detect::pe::detect_executable_architecture_for_host(Cursor::new(buf), host)
+                .context(DetectSnafu)
+        });
+        libraries.push(NativeLibrary {
+            location,
+            architecture,
+        });
+    }
+}
+
+fn read_entry(entry: &mut zip::read::ZipFile<'_, fs::File>, jar: &Path) -> Result<Vec<u8>> {
+    // the declared size is only a hint, the read itself is bounded as well
+    if entry.size() > MAX_ENTRY_SIZE {
+        return TooLargeSnafu {
+            path: jar,
+            size: entry.size(),
+        }
+        .fail();
+    }
+    let mut buf = Vec::new();
+    entry
+        .take(MAX_ENTRY_SIZE + 1)
+        .read_to_end(&mut buf)
+        .context(IoSnafu { path: jar })?;
+    if buf.len() as u64 > MAX_ENTRY_SIZE {
+        return TooLargeSnafu {
+            path: jar,
+            size: buf.len() as u64,
+        }
+        .fail();
+    }
+    Ok(buf)
+}
+
+fn extension_lowercase(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("IO error while reading {}: {}", path.display(), source))]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("failed to read jar {}: {}", path.display(), source))]
+    Zip {
+        source: zip::result::ZipError,
+        path: PathBuf,
+    },
+    #[snafu(display("entry of {} bytes in jar {} is too large", size, path.display()))]
+    TooLarge { size: u64, path: PathBuf },
+    #[snafu(display("{}", source))]
+    Detect { source: detect::Error },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const PE_X64: &[u8] = include_bytes!("../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+    const PE_ARM64: &[u8] = include_bytes!("../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let home = root.join("runtime");
+        fs::create_dir_all(home.join("bin")).unwrap();
+        fs::write(
+            home.join("release"),
+            "JAVA_VERSION=\"21.0.2\"\nOS_ARCH=\"aarch64\"\n",
+        )
+        .unwrap();
+        fs::write(home.join("bin").join("java.exe"), PE_ARM64).unwrap();
+        // libraries of the runtime itself are not JNI libraries of the application
+        fs::write(home.join("bin").join("jli.dll"), PE_X64).unwrap();
+        fs::write(root.join("native.dll"), PE_X64).unwrap();
+
+        let mut jar = zip::ZipWriter::new(fs::File::create(root.join("app.jar")).unwrap());
+        jar.start_file(
+            "natives/windows-arm64/lib.dll",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        jar.write_all(PE_ARM64).unwrap();
+        jar.finish().unwrap();
+        // a corrupt jar is reported on its own and does not end the scan
+        fs::write(root.join("broken.jar"), b"not a zip").unwrap();
+
+        let report = scan(root, Architecture::Arm64);
+
+        assert_eq!(report.jres.len(), 1);
+        let jre = &report.jres[0];
+        let release = jre.release.as_ref().unwrap();
+        assert_eq!(release.java_version.as_deref(), Some("21.0.2"));
+        assert_eq!(release.os_arch.as_deref(), Some("aarch64"));
+        assert_eq!(*jre.architecture.as_ref().unwrap(), Architecture::Arm64);
+
+        assert_eq!(report.libraries.len(), 3);
+        for lib in &report.libraries {
+            match &lib.location {
+                Location::File(path) if path.file_name().unwrap() == "broken.jar" => {
+                    assert!(matches!(lib.architecture, Err(Error::Zip { .. })));
+                    assert!(lib.incompatible_jres(&report.jres).is_empty());
+                }
+                Location::File(path) => {
+                    assert_eq!(path.file_name().unwrap(), "native.dll");
+                    assert_eq!(lib.incompatible_jres(&report.jres).len(), 1);
+                }
+                Location::JarEntry { entry, .. } => {
+                    assert_eq!(entry, "natives/windows-arm64/lib.dll");
+                    assert!(lib.incompatible_jres(&report.jres).is_empty());
+                }
+            }
+        }
+    }
+}
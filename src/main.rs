@@ -1,71 +1,769 @@
-use std::sync::LazyLock;
+use std::{
+    num::NonZeroUsize,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use comfy_table::Table;
 use palc::Parser;
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
 
-use crate::architecture::Architecture;
+use crate::architecture::{Architecture, Compatibility, Host, Verdict};
 
 mod architecture;
+mod cache;
+mod dependency;
 mod detect;
 mod executable;
+mod inf;
+mod java;
+mod output;
+mod plugin;
+mod policy;
+mod pool;
 mod process;
+mod redist;
+mod search_path;
+mod sibling;
+mod signature;
+mod tree;
+mod which;
 
 static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
-fn main() -> Result<()> {
-    if !ARGS.no_processes {
-        println!("current running processes:\n{}", detect_processes()?);
+/// Exit codes when a policy is given, tool failures exit with 1 as any error returned from
+/// `main` does.
+const EXIT_VIOLATIONS: u8 = 2;
+const EXIT_SCAN_ERRORS: u8 = 3;
+/// Exit code when the scan is interrupted by Ctrl-C, as shells report a process killed by it.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Set by the first Ctrl-C, the scan stops and prints what it found so far.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+fn main() -> Result<ExitCode> {
+    let mut scan = None;
+    if let Some(command) = &ARGS.command {
+        match command {
+            Command::Scan { paths, recursive } => scan = Some((paths, *recursive)),
+            Command::Java { root } => detect_java(root)?,
+            Command::Plugins { daw, dirs } => detect_plugins(*daw, dirs)?,
+            Command::Deps { exe, root } => detect_dependencies(exe, root.as_deref())?,
+            Command::Redist { dirs } => detect_redistributables(dirs)?,
+            Command::Drivers { dirs } => detect_driver_packages(dirs)?,
+            Command::Which { name } => detect_command(name)?,
+        };
+        // `scan` goes on to the same pipeline as `PATH`
+        if scan.is_none() {
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+    // a second Ctrl-C exits right away
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::Relaxed) {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+    })
+    .context(SignalSnafu)?;
+    let policy = ARGS
+        .policy
+        .as_deref()
+        .map(policy::Policy::load)
+        .transpose()?;
+    let cache = (!ARGS.no_cache)
+        .then(cache::Cache::default_path)
+        .flatten()
+        .map(|path| {
+            cache::Cache::load(&path).unwrap_or_else(|e| {
+                eprintln!("starting with an empty cache, {e}");
+                cache::Cache::empty(&path)
+            })
+        });
+    let mut out = output::Writer::new(
+        ARGS.format,
+        std::io::stdout().lock(),
+        hosts(),
+        ARGS.hide_errors,
+    )?;
+    if ARGS.tree {
+        out = out.with_tree(tree::Options {
+            collapse_native: ARGS.collapse_native,
+        });
+    }
+    if !ARGS.no_processes && scan.is_none() {
+        #[cfg(windows)]
+        detect_processes(&mut out, &process::System, cache.as_ref())?;
+        #[cfg(not(windows))]
+        eprintln!("running processes can only be listed on Windows");
+    }
+    if let Some((paths, recursive)) = scan {
+        out.begin(output::Section::Executable, "executables found");
+        let executables = executable::enumrate_paths(paths, recursive).map(|path| (path, None));
+        detect_executables(&mut out, executables, cache.as_ref(), policy.as_ref())?;
+    } else if !ARGS.no_executables {
+        out.begin(output::Section::Executable, "executables found in PATH");
+        let executables = executable::enumrate_executables(ARGS.registry_path)?
+            .map(|(path, source)| (path, Some(source)));
+        detect_executables(&mut out, executables, cache.as_ref(), policy.as_ref())?;
+    }
+    // results of an interrupted scan are as good as any
+    if let Some(Err(e)) = cache.map(cache::Cache::save) {
+        eprintln!("failed to save the cache, {e}");
+    }
+    let totals = out.finish()?;
+    if CANCELLED.load(Ordering::Relaxed) {
+        eprintln!("interrupted, the results are incomplete");
+        return Ok(ExitCode::from(EXIT_INTERRUPTED));
     }
-    if !ARGS.no_executables {
-        println!("executables found in PATH:\n{}", detect_executables()?);
+    if policy.is_none() {
+        return Ok(ExitCode::SUCCESS);
+    }
+    // only the executables are subject to the policy
+    let executables = totals
+        .iter()
+        .filter(|totals| totals.section == output::Section::Executable);
+    Ok(if executables.clone().any(|totals| totals.violations > 0) {
+        ExitCode::from(EXIT_VIOLATIONS)
+    } else if executables.clone().any(|totals| totals.failed > 0) {
+        ExitCode::from(EXIT_SCAN_ERRORS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// The hosts to evaluate against, the current system unless `--host` is given.
+fn hosts() -> Vec<Host> {
+    if ARGS.host.is_empty() {
+        vec![Host::current()]
+    } else {
+        ARGS.host.clone()
+    }
+}
+
+/// The host for subcommands that evaluate against a single one.
+fn host() -> Host {
+    hosts()[0]
+}
+
+/// Whether an image of `arch` is selected by `--arch`.
+///
+/// ARM64EC images carry the x64 machine type, so when the file is at hand x64 images with
+/// hybrid metadata are selected by `arm64ec` too.
+fn is_selected(arch: Architecture, path: Option<&std::path::Path>) -> bool {
+    ARGS.arch.is_empty()
+        || ARGS.arch.contains(&arch)
+        || (arch == Architecture::Amd64
+            && ARGS.arch.contains(&Architecture::Arm64Ec)
+            && path
+                .is_some_and(|path| detect::pe::is_hybrid_executable_file(path).unwrap_or(false)))
+}
+
+/// Whether a row is hidden, i.e. native on every host and `--all` is not given.
+fn is_hidden(compatibilities: &[Compatibility]) -> bool {
+    !ARGS.all && compatibilities.iter().all(|c| c.verdict == Verdict::Native)
+}
+
+#[cfg(windows)]
+fn detect_processes(
+    out: &mut output::Writer<impl std::io::Write>,
+    backend: &impl process::Backend,
+    cache: Option<&cache::Cache>,
+) -> Result<()> {
+    let hosts = hosts();
+    let architectures = hosts
+        .iter()
+        .map(|host| host.architecture)
+        .collect::<Vec<_>>();
+    out.begin(output::Section::Process, "current running processes");
+    for process in backend.processes()? {
+        let inspection = process::inspect(backend, process, &architectures, cache, ARGS.hash);
+        let mut record = output::Record {
+            section: output::Section::Process,
+            pid: Some(inspection.pid),
+            parent_pid: Some(inspection.parent_pid),
+            path: inspection.path,
+            path_source: None,
+            duplicates: vec![],
+            architecture: None,
+            kind: None,
+            sha256: None,
+            hosts: vec![],
+            modules: vec![],
+            error: None,
+            policy: None,
+        };
+        match inspection.infos {
+            Ok(infos) => {
+                let info = infos[0];
+                let image_path = inspection.image_path.as_deref();
+                if !is_selected(info.architecture, image_path) {
+                    out.hidden();
+                    continue;
+                }
+                let compatibilities = infos
+                    .iter()
+                    .zip(&hosts)
+                    .map(|(info, host)| Compatibility::of(info, host))
+                    .collect::<Vec<_>>();
+                if ARGS.modules {
+                    // processes that cannot be read without elevation are left without modules
+                    record.modules =
+                        process::foreign_modules(backend, inspection.pid, info.architecture, cache)
+                            .unwrap_or_default();
+                }
+                // the tree needs native parents to hang the others from
+                if !ARGS.tree && is_hidden(&compatibilities) && record.modules.is_empty() {
+                    out.hidden();
+                    continue;
+                }
+                record.architecture = Some(info.architecture);
+                record.kind = Some(info.kind);
+                record.sha256 = inspection.sha256;
+                record.hosts = hosts
+                    .iter()
+                    .zip(compatibilities)
+                    .map(|(host, c)| {
+                        let mut verdict = output::HostVerdict::new(host, c);
+                        if c.verdict != Verdict::Native
+                            && let Some(path) = image_path
+                        {
+                            verdict.native_alternative =
+                                cache::native_alternative(cache, path, host)
+                                    .map(|path| path.display().to_string());
+                        }
+                        verdict
+                    })
+                    .collect();
+            }
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        out.record(record)?;
     }
+    out.end()?;
     Ok(())
 }
 
-fn detect_processes() -> Result<Table> {
-    let mut table = Table::new();
-    table.set_header(vec![
-        "PID".to_string(),
-        "Executable".to_string(),
-        "Architecture".to_string(),
-    ]);
-    let processes = process::enumrate_running_processes()?;
-    for process in processes {
-        let process = process?;
-        match detect::process::detect_executable_architecture_by_pid(process.pid) {
+/// What became of an executable inspected.
+enum Inspected {
+    /// Counted but not shown, as native or not of an architecture selected by `--arch`.
+    Hidden,
+    Shown(output::Record),
+}
+
+fn detect_executables(
+    out: &mut output::Writer<impl std::io::Write>,
+    executables: impl Iterator<Item = (PathBuf, Option<search_path::Source>)> + Send,
+    cache: Option<&cache::Cache>,
+    policy: Option<&policy::Policy>,
+) -> Result<()> {
+    let hosts = hosts();
+    let options = pool::Options {
+        jobs: ARGS
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get),
+        ordered: !ARGS.unordered,
+        progress: !ARGS.no_progress,
+    };
+    pool::run(
+        executables,
+        options,
+        &CANCELLED,
+        |(exe_path, source)| inspect_executable(exe_path, *source, &hosts, cache, policy),
+        |_, inspected| match inspected {
+            Inspected::Hidden => {
+                out.hidden();
+                Ok(())
+            }
+            Inspected::Shown(record) => out.record(record),
+        },
+    )?;
+    out.end()?;
+    Ok(())
+}
+
+/// Detect the executable at `exe_path`, found through the `PATH` of `source` if any, for each
+/// of `hosts`, run on the worker threads.
+fn inspect_executable(
+    exe_path: &std::path::Path,
+    source: Option<search_path::Source>,
+    hosts: &[Host],
+    cache: Option<&cache::Cache>,
+    policy: Option<&policy::Policy>,
+) -> Inspected {
+    let mut record = output::Record {
+        section: output::Section::Executable,
+        pid: None,
+        parent_pid: None,
+        path: exe_path.display().to_string(),
+        path_source: source,
+        duplicates: vec![],
+        architecture: None,
+        kind: None,
+        sha256: None,
+        hosts: vec![],
+        modules: vec![],
+        error: None,
+        policy: None,
+    };
+    let architectures = hosts
+        .iter()
+        .map(|host| host.architecture)
+        .collect::<Vec<_>>();
+    let detected = match cache::detect(cache, exe_path, &architectures, ARGS.hash) {
+        Ok(detected) => detected,
+        Err(e) => {
+            record.error = Some(e.to_string());
+            return Inspected::Shown(record);
+        }
+    };
+    let infos = detected.infos;
+    let info = infos[0];
+    let compatibilities = infos
+        .iter()
+        .zip(hosts)
+        .map(|(info, host)| Compatibility::of(info, host))
+        .collect::<Vec<_>>();
+    let native = compatibilities.iter().all(|c| c.verdict == Verdict::Native);
+    record.policy = policy.map(|policy| {
+        policy.evaluate(
+            exe_path,
+            info.architecture,
+            native,
+            detected.sha256.as_deref(),
+        )
+    });
+    // the policy sees every executable, `--arch` only narrows what else is shown
+    let violation = record.policy.is_some_and(|decision| !decision.allowed);
+    let shown = violation
+        || is_selected(info.architecture, Some(exe_path))
+            && if info.is_architecture_neutral() {
+                ARGS.show_resource_only
+            } else {
+                !is_hidden(&compatibilities)
+            };
+    if !shown {
+        return Inspected::Hidden;
+    }
+    record.architecture = Some(info.architecture);
+    record.kind = Some(info.kind);
+    record.sha256 = detected.sha256;
+    record.hosts = hosts
+        .iter()
+        .zip(compatibilities)
+        .map(|(host, c)| {
+            let mut verdict = output::HostVerdict::new(host, c);
+            if c.verdict != Verdict::Native && !info.is_architecture_neutral() {
+                verdict.native_alternative = cache::native_alternative(cache, exe_path, host)
+                    .map(|path| path.display().to_string());
+            }
+            verdict
+        })
+        .collect();
+    Inspected::Shown(record)
+}
+
+fn detect_java(root: &std::path::Path) -> Result<()> {
+    let report = java::scan(root, host().architecture);
+
+    let mut jres = Table::new();
+    jres.set_header(vec!["Runtime", "Version", "OS_ARCH", "Architecture"]);
+    for jre in &report.jres {
+        let (version, os_arch) = match &jre.release {
+            Ok(release) => (
+                release.java_version.clone().unwrap_or_default(),
+                release.os_arch.clone().unwrap_or_default(),
+            ),
+            Err(e) => (e.to_string(), String::new()),
+        };
+        jres.add_row(vec![
+            jre.home.display().to_string(),
+            version,
+            os_arch,
+            match &jre.architecture {
+                Ok(arch) => arch.to_string(),
+                Err(e) => e.to_string(),
+            },
+        ]);
+    }
+    println!("bundled java runtimes:\n{jres}");
+
+    let mut libraries = Table::new();
+    libraries.set_header(vec!["Library", "Architecture", "Problem"]);
+    for library in &report.libraries {
+        let (arch, problem) = match &library.architecture {
             Ok(arch) => {
-                if !ARGS.all && arch == Architecture::current() {
+                let incompatible = library.incompatible_jres(&report.jres);
+                if !ARGS.all && incompatible.is_empty() {
                     continue;
                 }
-                table.add_row(vec![
-                    process.pid.to_string(),
-                    process.exe_path,
-                    arch.to_string(),
-                ]);
+                let problem = incompatible
+                    .iter()
+                    .map(|jre| format!("cannot be loaded by {}", jre.home.display()))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (arch.to_string(), problem)
             }
-            Err(_) => {
-                // eprintln!("failed detect {}({}), {}", process.exe_path, process.pid, e);
+            Err(e) => (String::new(), e.to_string()),
+        };
+        libraries.add_row(vec![library.location.to_string(), arch, problem]);
+    }
+    println!("native libraries:\n{libraries}");
+    Ok(())
+}
+
+fn detect_plugins(daw: Option<Architecture>, dirs: &[PathBuf]) -> Result<()> {
+    let host = host().architecture;
+    let daw = daw.unwrap_or(host);
+    if !plugin::HOSTS.contains(&daw) {
+        return EmptySnafu {
+            msg: format!("there are no plugin hosts of {daw} architecture, specify `--daw`"),
+        }
+        .fail();
+    }
+    let locations = if dirs.is_empty() {
+        plugin::standard_locations()
+            .into_iter()
+            .map(|(format, dir)| (dir, format == plugin::Format::Vst2))
+            .collect::<Vec<_>>()
+    } else {
+        dirs.iter().map(|dir| (dir.clone(), true)).collect()
+    };
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Format".to_string(),
+        "Plugin".to_string(),
+        "Binaries".to_string(),
+        format!("Loadable by {daw}"),
+    ]);
+    for (dir, vst2_dlls) in locations {
+        for plugin in plugin::scan(&dir, vst2_dlls, host) {
+            let loadable = plugin.loadable_by(daw);
+            if !ARGS.all && loadable && !plugin.binaries.iter().any(|b| b.is_misplaced()) {
+                continue;
             }
+            let binaries = plugin
+                .binaries
+                .iter()
+                .map(|b| {
+                    let arch = match &b.architecture {
+                        Ok(arch) => arch.to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    let misplaced = if b.is_misplaced() { " (misplaced)" } else { "" };
+                    match &b.folder {
+                        Some(folder) => format!("{folder}: {arch}{misplaced}"),
+                        None => arch,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            table.add_row(vec![
+                plugin.format.to_string(),
+                plugin.path.display().to_string(),
+                binaries,
+                if loadable { "yes" } else { "no" }.to_string(),
+            ]);
         }
     }
-    Ok(table)
+    println!("audio plugins:\n{table}");
+    Ok(())
 }
 
-fn detect_executables() -> Result<Table> {
+fn detect_command(name: &str) -> Result<()> {
+    let host = host();
+    let dirs = search_path::entries(ARGS.registry_path).context(SearchPathSnafu)?;
+    let candidates = which::resolve(name, &dirs, &which::extensions(), &host);
+    if candidates.is_empty() {
+        return EmptySnafu {
+            msg: format!("`{name}` is not found in PATH"),
+        }
+        .fail();
+    }
+
     let mut table = Table::new();
-    table.set_header(vec!["Executable".to_string(), "Architecture".to_string()]);
+    table.set_header(vec!["", "Path", "PATH", "Architecture", "Verdict"]);
+    for (index, candidate) in candidates.iter().enumerate() {
+        let (arch, verdict) = match &candidate.image {
+            Some(Ok((info, compatibility))) => (
+                info.architecture.to_string(),
+                compatibility.verdict.to_string(),
+            ),
+            Some(Err(e)) => (String::new(), e.to_string()),
+            None => (
+                String::new(),
+                "not an image, runs through its interpreter".to_string(),
+            ),
+        };
+        table.add_row(vec![
+            if index == 0 { "runs" } else { "shadowed" }.to_string(),
+            candidate.path.display().to_string(),
+            candidate.source.to_string(),
+            arch,
+            verdict,
+        ]);
+    }
+    println!("`{name}` resolves to, on {host}:\n{table}");
+    if let Some(native) = which::shadowed_native(&candidates) {
+        println!(
+            "a native build at {} is shadowed by {}, which is {}. Move its directory earlier in PATH to run it.",
+            native.path.display(),
+            candidates[0].path.display(),
+            candidates[0]
+                .verdict()
+                .map_or("not an image".to_string(), |verdict| verdict.to_string()),
+        );
+    }
+    Ok(())
+}
+
+fn detect_dependencies(exe: &std::path::Path, root: Option<&std::path::Path>) -> Result<()> {
+    let path_dirs = executable::path_dirs()?;
+    let resolver = match root {
+        Some(root) => dependency::Resolver::new(
+            root.to_path_buf(),
+            host().architecture,
+            path_dirs
+                .iter()
+                .map(|dir| dependency::rebase(dir, root))
+                .collect(),
+        ),
+        None => dependency::Resolver::new(
+            dependency::Resolver::system_root(),
+            host().architecture,
+            path_dirs,
+        ),
+    };
+    let tree = resolver.analyze(exe)?;
+    let mut out = String::new();
+    print_dependency(&tree, "", "", &mut out);
+    print!("{out}");
+    Ok(())
+}
+
+fn print_dependency(dep: &dependency::Dependency, first: &str, rest: &str, out: &mut String) {
+    use std::fmt::Write;
+
+    let _ = write!(out, "{first}{}", dep.name);
+    if dep.delay_load {
+        out.push_str(" (delay-load)");
+    }
+    match &dep.resolution {
+        dependency::Resolution::ApiSet => out.push_str(" [api set]"),
+        dependency::Resolution::Missing => out.push_str(" => MISSING"),
+        dependency::Resolution::Found {
+            path,
+            architecture,
+            loadable,
+            repeated,
+        } => {
+            let _ = write!(out, " => {}", path.display());
+            match architecture {
+                Ok(arch) => {
+                    let _ = write!(out, " [{arch}]");
+                }
+                Err(e) => {
+                    let _ = write!(out, " [{e}]");
+                }
+            }
+            if !loadable {
+                out.push_str(" MISMATCH");
+            }
+            if *repeated {
+                out.push_str(" (see above)");
+            }
+        }
+    }
+    if let Some(e) = &dep.error {
+        let _ = write!(out, " (failed reading imports: {e})");
+    }
+    out.push('\n');
+
+    let children = dep
+        .children
+        .iter()
+        .filter(|c| ARGS.all || c.has_problem())
+        .collect::<Vec<_>>();
+    for (i, child) in children.iter().enumerate() {
+        let (first, next) = if i + 1 == children.len() {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        print_dependency(
+            child,
+            &format!("{rest}{first}"),
+            &format!("{rest}{next}"),
+            out,
+        );
+    }
+}
+
+fn detect_redistributables(dirs: &[PathBuf]) -> Result<()> {
+    let executables: Box<dyn Iterator<Item = PathBuf>> = if dirs.is_empty() {
+        Box::new(executable::enumrate_executables(false)?.map(|(path, _)| path))
+    } else {
+        Box::new(
+            dirs.iter()
+                .flat_map(|dir| executable::walk_files(dir))
+                .filter(|path| executable::has_executable_extension(path)),
+        )
+    };
+    let host = host();
+    let resolver = dependency::Resolver::new(
+        dependency::Resolver::system_root(),
+        host.architecture,
+        Vec::new(),
+    );
 
-    let executables = executable::enumrate_executables()?;
+    struct Missing {
+        arch: Architecture,
+        name: &'static str,
+        dlls: Vec<String>,
+        needed_by: Vec<PathBuf>,
+    }
+    let mut missing: Vec<Missing> = Vec::new();
+    let mut failures = Table::new();
+    failures.set_header(vec!["Path", "Error"]);
     for exe_path in executables {
-        if let Ok(arch) = detect::pe::detect_executable_architecture_file(&exe_path) {
-            if !ARGS.all && arch == Architecture::current() {
+        let info = match detect::pe::detect_image_info_file(&exe_path, host.architecture) {
+            Ok(info) => info,
+            Err(e) => {
+                failures.add_row(vec![exe_path.display().to_string(), e.to_string()]);
                 continue;
             }
-            table.add_row(vec![exe_path.display().to_string(), arch.to_string()]);
+        };
+        // only non-native executables are checked, as told by the verdict, which takes Any CPU
+        // and ARM64X images into account
+        if Compatibility::of(&info, &host).verdict == Verdict::Native {
+            continue;
+        }
+        let arch = info.architecture;
+        let gaps = match redist::find_gaps(&exe_path, arch, &resolver) {
+            Ok(gaps) => gaps,
+            Err(e) => {
+                failures.add_row(vec![exe_path.display().to_string(), e.to_string()]);
+                continue;
+            }
+        };
+        for gap in gaps {
+            let name = gap.redistributable.name;
+            let index = match missing
+                .iter()
+                .position(|m| m.arch == arch && m.name == name)
+            {
+                Some(index) => index,
+                None => {
+                    missing.push(Missing {
+                        arch,
+                        name,
+                        dlls: vec![],
+                        needed_by: vec![],
+                    });
+                    missing.len() - 1
+                }
+            };
+            let entry = &mut missing[index];
+            if !entry.dlls.iter().any(|d| d.eq_ignore_ascii_case(&gap.dll)) {
+                entry.dlls.push(gap.dll);
+            }
+            if !entry.needed_by.contains(&exe_path) {
+                entry.needed_by.push(exe_path.clone());
+            }
         }
     }
 
-    Ok(table)
+    let mut table = Table::new();
+    table.set_header(vec!["Redistributable", "Missing DLLs", "Needed by"]);
+    for entry in missing {
+        table.add_row(vec![
+            format!("{} ({})", entry.name, entry.arch),
+            entry.dlls.join("\n"),
+            entry
+                .needed_by
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ]);
+    }
+    println!("redistributables to install:\n{table}");
+    if !failures.is_empty() {
+        println!("could not inspect:\n{failures}");
+    }
+    Ok(())
+}
+
+fn detect_driver_packages(dirs: &[PathBuf]) -> Result<()> {
+    let dirs = if dirs.is_empty() {
+        vec![inf::driver_store()]
+    } else {
+        dirs.to_vec()
+    };
+    let host = host().architecture;
+    let mut table = Table::new();
+    table.set_header(vec!["INF", "Provider", "Platforms", "Problems"]);
+    for inf_path in dirs
+        .iter()
+        .flat_map(|dir| executable::walk_files(dir))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("inf"))
+        })
+    {
+        let package = match inf::Package::check(&inf_path, host) {
+            Ok(package) => package,
+            Err(e) => {
+                table.add_row(vec![
+                    inf_path.display().to_string(),
+                    String::new(),
+                    String::new(),
+                    e.to_string(),
+                ]);
+                continue;
+            }
+        };
+        let mut problems = Vec::new();
+        if !package.supports_arm64() {
+            problems.push("no ARM64 decoration".to_string());
+        }
+        for file in &package.files {
+            match &file.architecture {
+                None => problems.push(format!(
+                    "{} for {} not found",
+                    file.name.display(),
+                    file.platform
+                )),
+                Some(Err(e)) => problems.push(format!("{}: {e}", file.name.display())),
+                Some(Ok(arch)) if file.is_mismatched() => problems.push(format!(
+                    "{} for {} is {arch}",
+                    file.name.display(),
+                    file.platform
+                )),
+                Some(Ok(_)) => {}
+            }
+        }
+        if !ARGS.all && problems.is_empty() {
+            continue;
+        }
+        table.add_row(vec![
+            package.inf_path.display().to_string(),
+            package.provider.unwrap_or_default(),
+            package
+                .decorations
+                .iter()
+                .map(|d| d.raw.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            problems.join("\n"),
+        ]);
+    }
+    println!("driver packages:\n{table}");
+    Ok(())
 }
 
 #[derive(Debug, palc::Parser)]
@@ -82,6 +780,179 @@ struct Args {
     /// show all results, by default only result that are not same with current machines architecture are shown
     #[arg(short, long)]
     all: bool,
+    /// Show resource-only images, such as MUI files and icon libraries
+    ///
+    /// They contain no code and load into processes of any architecture, so they are hidden by default.
+    #[arg(long)]
+    show_resource_only: bool,
+    /// Evaluate against this host instead of the current system, as `<arch>[:<build>]`
+    ///
+    /// e.g. `arm64:22631` for a Windows 11 23H2 ARM64 laptop. Give it more than once to compare
+    /// several hosts, with a column per host. Subcommands checking a single host use the first.
+    #[arg(long)]
+    host: Vec<Host>,
+    /// Only show processes and executables of these architectures, e.g. `x64,arm64ec`
+    ///
+    /// Accepts the names shown in the output as well as common aliases such as `amd64`,
+    /// `x86_64`, `aarch64` and `i386`. Policy violations are shown regardless.
+    #[arg(long, use_value_delimiter = true)]
+    arch: Vec<Architecture>,
+    /// How to print the processes and executables found
+    ///
+    /// `json`, `ndjson` and `csv` follow the versioned schema described in the README, `ndjson`
+    /// and `csv` are written as results come in.
+    #[arg(long, default_value = "table")]
+    format: output::Format,
+    /// Leave out processes and executables that could not be inspected
+    ///
+    /// e.g. protected processes and files that are not valid PE images. They are still counted
+    /// in the totals.
+    #[arg(long)]
+    hide_errors: bool,
+    /// Nest processes under the process that started them, native ones included
+    ///
+    /// Only tables are drawn as a tree, the other formats carry `parent_pid` instead.
+    /// Processes whose parent has exited are shown at the top level.
+    #[arg(long)]
+    tree: bool,
+    /// With `--tree`, fold subtrees of only native processes into their root
+    #[arg(long)]
+    collapse_native: bool,
+    /// Inspect the modules loaded into each process, and list those not matching it
+    ///
+    /// e.g. x64 plugins in an ARM64EC process, or CHPE modules in an x86 one. Native processes
+    /// loading such modules are shown too. This reads every loaded DLL, and processes that
+    /// cannot be read without elevation are listed without modules.
+    #[arg(long)]
+    #[cfg_attr(not(windows), allow(dead_code))]
+    modules: bool,
+    /// The number of executables to inspect at once, defaults to the number of CPUs
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+    /// List executables in the order their inspection completes rather than the order they are found
+    ///
+    /// `--format ndjson` and `--format csv` then stream results without waiting on slow files.
+    #[arg(long)]
+    unordered: bool,
+    /// Do not show the number of files found and inspected so far on stderr
+    ///
+    /// It is only shown when stderr is a terminal. Ctrl-C stops the scan and prints the
+    /// results found so far.
+    #[arg(long)]
+    no_progress: bool,
+    /// Look for executables in the machine and user `PATH` stored in the registry
+    ///
+    /// Rather than the `PATH` this process inherited. Results tell which of the two an
+    /// executable was found through. Only on Windows.
+    #[arg(long)]
+    registry_path: bool,
+    /// Take the SHA-256 of each executable, and list executables with the same contents once
+    ///
+    /// The hash is included in the machine readable formats. Tables and `--format json` list
+    /// all locations of the same contents in one entry, `ndjson` and `csv` have a record per
+    /// location.
+    #[arg(long)]
+    hash: bool,
+    /// Inspect every executable again rather than reusing results of earlier scans
+    ///
+    /// Results are cached in `%LOCALAPPDATA%\WoArchiTect\cache.json`, or `~/.cache` outside
+    /// Windows, and reused for files whose size and modification time are unchanged.
+    #[arg(long)]
+    no_cache: bool,
+    /// Check the executables found against a policy file, for use as a CI gate
+    ///
+    /// Exits with 2 when any executable is denied by the policy, with 3 when none is but some
+    /// could not be inspected, and with 1 when the scan itself fails. See the README for the
+    /// policy format.
+    #[arg(long)]
+    policy: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, palc::Subcommand)]
+enum Command {
+    /// Check files and directories instead of the executables found in `PATH`
+    ///
+    /// e.g. `C:\Program Files`, a mounted image or a single binary. Results are filtered,
+    /// printed and checked against `--policy` as for `PATH`, with the options given before
+    /// `scan`. Running processes are not listed.
+    // a subcommand rather than positional paths on `Args`, as palc takes no list of positional
+    // arguments next to subcommands
+    Scan {
+        /// Files and directories to scan
+        ///
+        /// Files are inspected whatever their extension, directories are searched for `.exe`,
+        /// `.dll` and `.sys` files.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Look into subdirectories of the directories given
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Check Java runtimes bundled with an application against its JNI libraries
+    ///
+    /// Runtimes are located by their `release` file and `bin\java.exe`. Native libraries are
+    /// `.dll` files in the application directory and `.dll` entries inside its jars.
+    Java {
+        /// The application directory to scan
+        root: PathBuf,
+    },
+    /// Check which VST2, VST3 and CLAP plugins a DAW of the given architecture can load
+    ///
+    /// By default the standard plugin install locations are scanned. VST3 bundles are checked
+    /// per `Contents\<arch>-win` folder, and binaries not matching their folder are reported as misplaced.
+    Plugins {
+        /// Architecture of the DAW, one of x86, x64, arm64 or arm64ec, defaults to the native
+        /// architecture of the host
+        ///
+        /// This is the process the plugins load into, unlike the global `--host`, which is the
+        /// system it runs on.
+        #[arg(long)]
+        daw: Option<Architecture>,
+        /// Directories to scan instead of the standard locations
+        dirs: Vec<PathBuf>,
+    },
+    /// Show the DLL dependency tree of an executable with missing or mismatched DLLs
+    ///
+    /// Imports and delay-load imports are resolved through the application directory, the
+    /// system directory of the executable's architecture, the Windows directory and `PATH`.
+    /// Only branches containing problems are shown, unless `--all` is given.
+    Deps {
+        /// The executable to analyze
+        exe: PathBuf,
+        /// Root of the Windows installation to resolve against, defaults to the system drive
+        ///
+        /// `PATH` entries are mapped onto this root as well.
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+    /// List the runtime redistributables non-native executables need but lack
+    ///
+    /// Imports of Visual C++, Universal C Runtime and DirectX runtime DLLs are checked for a copy
+    /// matching the executable's architecture, in its own directory or the system directory.
+    Redist {
+        /// Directories to scan recursively, defaults to executables found in `PATH`
+        dirs: Vec<PathBuf>,
+    },
+    /// Check the platforms covered by driver packages and the binaries they declare for each
+    ///
+    /// Packages without an ARM64 decoration in `[Manufacturer]`, and binaries listed in
+    /// `[SourceDisksFiles]` for a platform that are of another architecture, are reported.
+    Drivers {
+        /// Directories to search for INF files, defaults to the DriverStore
+        dirs: Vec<PathBuf>,
+    },
+    /// Show which file runs for a command name, and the files later in PATH it shadows
+    ///
+    /// The name is looked up in each `PATH` directory in order, with each `PATHEXT` extension
+    /// unless it carries one, as `cmd` does. A native build shadowed by an emulated one is
+    /// pointed out. `--registry-path` looks up the machine and user `PATH` instead.
+    Which {
+        /// The command, e.g. `git`
+        name: String,
+    },
 }
 
 #[derive(Debug, Snafu)]
@@ -95,5 +966,17 @@ pub enum Error {
     #[snafu(context(false))]
     #[snafu(display("when enumrating executables, {}", source))]
     EnumrateExecutables { source: executable::Error },
+    #[snafu(context(false))]
+    #[snafu(display("when writing results, {}", source))]
+    Output { source: output::Error },
+    #[snafu(context(false))]
+    #[snafu(display("when loading the policy, {}", source))]
+    Policy { source: policy::Error },
+    #[snafu(display("failed to handle Ctrl-C, {}", source))]
+    Signal { source: ctrlc::Error },
+    #[snafu(display("when reading PATH, {}", source))]
+    SearchPath { source: search_path::Error },
+    #[snafu(display("{}", msg))]
+    Empty { msg: String },
 }
 type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,856 @@
+//! Write the results of the process and executable scans as a table or in a machine readable
+//! format.
+//!
+//! The machine readable formats share one record layout, versioned by [`SCHEMA_VERSION`] and
+//! documented in the README. NDJSON and CSV are written as records come in, JSON and tables
+//! once a section or the whole scan is done.
+//!
+//! Images that could not be inspected are listed in a section of their own in tables, and as
+//! records with an `error` in the other formats. Either way they are counted in the totals.
+//!
+//! Images hashed with `--hash` are folded into the first one with the same contents in tables and
+//! JSON, which list all their locations. NDJSON and CSV cannot take back what they wrote, so they
+//! write a record per location to be grouped by `sha256`.
+//!
+//! With [`Writer::with_tree`] the process table nests children under their parents, other
+//! formats carry `parent_pid` to rebuild the tree from.
+
+use std::{collections::HashMap, io::Write, mem};
+
+use comfy_table::Table;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    architecture::{Architecture, Compatibility, Host, Verdict},
+    detect::pe::ImageKind,
+    policy::Decision,
+    process::Module,
+    search_path::Source,
+    tree,
+};
+
+/// Bumped whenever a field is removed or changes meaning, adding fields does not bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, palc::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Ndjson,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Section {
+    Process,
+    Executable,
+}
+
+impl Section {
+    fn noun(self) -> &'static str {
+        match self {
+            Section::Process => "processes",
+            Section::Executable => "executables",
+        }
+    }
+}
+
+/// How many images of a section were looked at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Totals {
+    pub section: Section,
+    /// Images inspected, whether shown or hidden as native or by `--arch`.
+    pub inspected: usize,
+    /// Images inspected and shown, i.e. not native on every host or `--all` given.
+    pub shown: usize,
+    /// Images that could not be inspected.
+    pub failed: usize,
+    /// Images shown as another location of an earlier one with the same contents.
+    pub duplicates: usize,
+    /// Images denied by the policy.
+    pub violations: usize,
+}
+
+impl std::fmt::Display for Totals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} inspected, {} shown",
+            self.section.noun(),
+            self.inspected,
+            self.shown
+        )?;
+        if self.duplicates > 0 {
+            write!(f, " ({} duplicates)", self.duplicates)?;
+        }
+        write!(f, ", {} could not be inspected", self.failed)?;
+        if self.violations > 0 {
+            write!(f, ", {} policy violations", self.violations)?;
+        }
+        Ok(())
+    }
+}
+
+/// A process or executable, as found by the scan.
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub section: Section,
+    pub pid: Option<u32>,
+    /// The PID of the process that started a process, which may have exited since.
+    pub parent_pid: Option<u32>,
+    /// The file path of an executable, or the image path of a process, its executable name
+    /// when the path cannot be queried.
+    pub path: String,
+    /// The `PATH` an executable was found through, when scanning `PATH`.
+    pub path_source: Option<Source>,
+    /// Other paths with the same contents, folded into this record.
+    pub duplicates: Vec<String>,
+    /// Missing when the image could not be inspected, see [`Self::error`].
+    pub architecture: Option<Architecture>,
+    pub kind: Option<ImageKind>,
+    /// The SHA-256 of an executable as lowercase hex, with `--hash`.
+    pub sha256: Option<String>,
+    /// The verdict for each host evaluated against, in the order given.
+    pub hosts: Vec<HostVerdict>,
+    /// With `--modules`, the modules loaded into a process that do not match it.
+    pub modules: Vec<Module>,
+    pub error: Option<String>,
+    /// The policy decision, when a policy is given.
+    pub policy: Option<Decision>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostVerdict {
+    pub host: Architecture,
+    pub build: u32,
+    pub verdict: Verdict,
+    pub reason: &'static str,
+    /// A build of the same binary installed alongside that is native on this host.
+    pub native_alternative: Option<String>,
+}
+
+impl HostVerdict {
+    pub fn new(host: &Host, compatibility: Compatibility) -> Self {
+        HostVerdict {
+            host: host.architecture,
+            build: host.build,
+            verdict: compatibility.verdict,
+            reason: compatibility.reason,
+            native_alternative: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    record: &'a Record,
+}
+
+const CSV_HEADER: &[&str] = &[
+    "schema_version",
+    "section",
+    "pid",
+    "path",
+    "architecture",
+    "kind",
+    "host",
+    "build",
+    "verdict",
+    "reason",
+    "error",
+    "policy_allowed",
+    "policy_rule",
+    "sha256",
+    "path_source",
+    "native_alternative",
+    "parent_pid",
+];
+
+pub struct Writer<W: Write> {
+    format: Format,
+    out: W,
+    hosts: Vec<Host>,
+    /// The section being written and its title.
+    section: Option<(Section, String)>,
+    /// Records held back for the table of a section, or for the JSON format, which is written
+    /// as a single array.
+    records: Vec<Record>,
+    /// The index in `records` of the first record with each hash.
+    hashes: HashMap<String, usize>,
+    /// Records that could not be inspected, held back for their own table.
+    failures: Vec<Record>,
+    /// Records denied by the policy, held back for their own table.
+    violations: Vec<Record>,
+    hide_errors: bool,
+    /// Lay out the process table as a tree.
+    tree: Option<tree::Options>,
+    totals: Vec<Totals>,
+}
+
+impl<W: Write> Writer<W> {
+    /// `hide_errors` leaves out images that could not be inspected, they are still counted.
+    pub fn new(format: Format, out: W, hosts: Vec<Host>, hide_errors: bool) -> Result<Self> {
+        let mut writer = Writer {
+            format,
+            out,
+            hosts,
+            section: None,
+            records: Vec::new(),
+            hashes: HashMap::new(),
+            failures: Vec::new(),
+            violations: Vec::new(),
+            hide_errors,
+            tree: None,
+            totals: Vec::new(),
+        };
+        if format == Format::Csv {
+            writer.write_csv_row(CSV_HEADER.iter().map(|s| s.to_string()))?;
+        }
+        Ok(writer)
+    }
+
+    /// Nest processes under their parents in tables.
+    pub fn with_tree(mut self, options: tree::Options) -> Self {
+        self.tree = Some(options);
+        self
+    }
+
+    /// Start a section, `title` heads its table.
+    pub fn begin(&mut self, section: Section, title: &str) {
+        self.section = Some((section, title.to_string()));
+        self.totals.push(Totals {
+            section,
+            inspected: 0,
+            shown: 0,
+            failed: 0,
+            duplicates: 0,
+            violations: 0,
+        });
+    }
+
+    /// Count an image that was inspected but is not shown.
+    pub fn hidden(&mut self) {
+        if let Some(totals) = self.totals.last_mut() {
+            totals.inspected += 1;
+        }
+    }
+
+    pub fn record(&mut self, record: Record) -> Result<()> {
+        if let Some(totals) = self.totals.last_mut() {
+            if record.error.is_some() {
+                totals.failed += 1;
+            } else {
+                totals.inspected += 1;
+                totals.shown += 1;
+            }
+            if record.policy.is_some_and(|decision| !decision.allowed) {
+                totals.violations += 1;
+            }
+        }
+        if record.error.is_some() && self.hide_errors {
+            return Ok(());
+        }
+        match self.format {
+            Format::Table => {
+                if record.error.is_some() {
+                    self.failures.push(record);
+                    return Ok(());
+                }
+                if record.policy.is_some_and(|decision| !decision.allowed) {
+                    self.violations.push(record.clone());
+                }
+                if let Some(record) = self.fold(record) {
+                    self.records.push(record);
+                }
+            }
+            Format::Json => {
+                if let Some(record) = self.fold(record) {
+                    self.records.push(record);
+                }
+            }
+            Format::Ndjson => {
+                serde_json::to_writer(
+                    &mut self.out,
+                    &Versioned {
+                        schema_version: SCHEMA_VERSION,
+                        record: &record,
+                    },
+                )
+                .context(JsonSnafu)?;
+                writeln!(self.out).context(IoSnafu)?;
+                self.out.flush().context(IoSnafu)?;
+            }
+            Format::Csv => {
+                let rows = csv_rows(&record);
+                for row in rows {
+                    self.write_csv_row(row.into_iter())?;
+                }
+                self.out.flush().context(IoSnafu)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn end(&mut self) -> Result<()> {
+        // records of different sections are not folded together
+        self.hashes.clear();
+        if let Some((section, title)) = self.section.take()
+            && self.format == Format::Table
+        {
+            let mut table = Table::new();
+            let mut header = vec![];
+            if section == Section::Process {
+                header.push("PID".to_string());
+            }
+            header.extend(["Executable".to_string(), "Architecture".to_string()]);
+            match self.hosts.as_slice() {
+                [_] => header.extend(["Verdict".to_string(), "Reason".to_string()]),
+                hosts => header.extend(hosts.iter().map(|host| host.to_string())),
+            }
+            table.set_header(header);
+            let records = mem::take(&mut self.records);
+            match self.tree {
+                Some(options) if section == Section::Process => {
+                    for row in tree_rows(&records, options) {
+                        table.add_row(row);
+                    }
+                }
+                _ => {
+                    for record in &records {
+                        table.add_row(table_row(record));
+                    }
+                }
+            }
+            writeln!(self.out, "{title}:\n{table}").context(IoSnafu)?;
+        }
+        Ok(())
+    }
+
+    /// Fold `record` into an earlier one with the same contents and policy decision, or hand
+    /// it back if there is none.
+    ///
+    /// Processes are never folded, each has its own PID. Records with a native alternative are
+    /// neither folded nor folded into, as the alternative is found next to each path.
+    fn fold(&mut self, record: Record) -> Option<Record> {
+        let Some(hash) = &record.sha256 else {
+            return Some(record);
+        };
+        if record.section == Section::Process {
+            return Some(record);
+        }
+        if record
+            .hosts
+            .iter()
+            .any(|verdict| verdict.native_alternative.is_some())
+        {
+            return Some(record);
+        }
+        match self.hashes.get(hash) {
+            Some(&index) if self.records[index].policy == record.policy => {
+                self.records[index].duplicates.push(record.path);
+                if let Some(totals) = self.totals.last_mut() {
+                    totals.duplicates += 1;
+                }
+                None
+            }
+            _ => {
+                self.hashes.insert(hash.clone(), self.records.len());
+                Some(record)
+            }
+        }
+    }
+
+    /// Write what is held back and the totals, which are returned.
+    pub fn finish(mut self) -> Result<Vec<Totals>> {
+        self.end()?;
+        if self.format == Format::Table {
+            if !self.violations.is_empty() {
+                let mut table = Table::new();
+                table.set_header(vec!["Executable", "Architecture", "Denied by"]);
+                for record in &self.violations {
+                    table.add_row(vec![
+                        record.path.clone(),
+                        record
+                            .architecture
+                            .map(|arch| arch.to_string())
+                            .unwrap_or_default(),
+                        match record.policy.and_then(|decision| decision.rule) {
+                            Some(rule) => format!("rule {rule}"),
+                            None => "default, not native".to_string(),
+                        },
+                    ]);
+                }
+                writeln!(self.out, "policy violations:\n{table}").context(IoSnafu)?;
+            }
+            if !self.failures.is_empty() {
+                let mut table = Table::new();
+                table.set_header(vec!["PID", "Path", "Error"]);
+                for record in &self.failures {
+                    table.add_row(vec![
+                        record.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+                        record.path.clone(),
+                        record.error.clone().unwrap_or_default(),
+                    ]);
+                }
+                writeln!(self.out, "could not inspect:\n{table}").context(IoSnafu)?;
+            }
+            for totals in &self.totals {
+                writeln!(self.out, "{totals}").context(IoSnafu)?;
+            }
+        } else {
+            // keep the machine readable output on stdout parseable
+            for totals in &self.totals {
+                eprintln!("{totals}");
+            }
+        }
+        if self.format == Format::Json {
+            let records = self
+                .records
+                .iter()
+                .map(|record| Versioned {
+                    schema_version: SCHEMA_VERSION,
+                    record,
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut self.out, &records).context(JsonSnafu)?;
+            writeln!(self.out).context(IoSnafu)?;
+        }
+        self.out.flush().context(IoSnafu)?;
+        Ok(self.totals)
+    }
+
+    fn write_csv_row(&mut self, fields: impl Iterator<Item = String>) -> Result<()> {
+        let line = fields
+            .map(|field| csv_field(&field))
+            .collect::<Vec<_>>()
+            .join(",");
+        // RFC 4180 lines end with CRLF
+        write!(self.out, "{line}\r\n").context(IoSnafu)
+    }
+}
+
+fn table_row(record: &Record) -> Vec<String> {
+    let mut row = vec![];
+    if let Some(pid) = record.pid {
+        row.push(pid.to_string());
+    }
+    let path = match record.path_source {
+        Some(source @ (Source::Machine | Source::User)) => {
+            format!("{} ({source} PATH)", record.path)
+        }
+        _ => record.path.clone(),
+    };
+    // all locations of the same contents, and the modules of a process not matching it
+    row.push(
+        std::iter::once(path)
+            .chain(record.duplicates.iter().cloned())
+            .chain(record.modules.iter().map(|module| {
+                format!(
+                    "loads {} ({}): {}",
+                    module.path.display(),
+                    module.architecture,
+                    module.reason
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
+    row.push(match (record.architecture, record.kind) {
+        (Some(arch), Some(ImageKind::ResourceOnly)) => format!("neutral ({arch}, resource only)"),
+        (Some(arch), Some(ImageKind::Driver)) => format!("{arch} (driver)"),
+        (Some(arch), _) => arch.to_string(),
+        (None, _) => String::new(),
+    });
+    let reason = |v: &HostVerdict| match &v.native_alternative {
+        Some(path) => format!("{}\nnative alternative available at {path}", v.reason),
+        None => v.reason.to_string(),
+    };
+    match record.hosts.as_slice() {
+        [v] => row.extend([v.verdict.to_string(), reason(v)]),
+        verdicts => row.extend(
+            verdicts
+                .iter()
+                .map(|v| format!("{}\n{}", v.verdict, reason(v))),
+        ),
+    }
+    row
+}
+
+/// The rows of `records` laid out as a tree, the tree drawn in the executable column.
+fn tree_rows(records: &[Record], options: tree::Options) -> Vec<Vec<String>> {
+    let nodes = records
+        .iter()
+        .map(|record| tree::Node {
+            pid: record.pid.unwrap_or_default(),
+            parent_pid: record.parent_pid,
+            native: record.hosts.iter().all(|v| v.verdict == Verdict::Native)
+                && record.modules.is_empty(),
+        })
+        .collect::<Vec<_>>();
+    tree::lines(&nodes, options)
+        .into_iter()
+        .map(|line| {
+            let mut row = table_row(&records[line.index]);
+            let mut path = format!("{}{}", line.prefix, row[1]);
+            if line.collapsed > 0 {
+                path.push_str(&format!(" (+{} native descendants)", line.collapsed));
+            }
+            row[1] = path;
+            row
+        })
+        .collect()
+}
+
+/// One row per host, or a single row without verdict for records that could not be inspected.
+fn csv_rows(record: &Record) -> Vec<Vec<String>> {
+    let common = [
+        SCHEMA_VERSION.to_string(),
+        csv_value(&record.section),
+        csv_value(&record.pid),
+        record.path.clone(),
+        csv_value(&record.architecture),
+        csv_value(&record.kind),
+    ];
+    let tail = [
+        record.error.clone().unwrap_or_default(),
+        csv_value(&record.policy.map(|decision| decision.allowed)),
+        csv_value(&record.policy.and_then(|decision| decision.rule)),
+        csv_value(&record.sha256),
+        csv_value(&record.path_source),
+    ];
+    if record.hosts.is_empty() {
+        let mut row = common.to_vec();
+        row.extend(["", "", "", ""].map(String::from));
+        row.extend(tail);
+        row.extend([String::new(), csv_value(&record.parent_pid)]);
+        return vec![row];
+    }
+    record
+        .hosts
+        .iter()
+        .map(|v| {
+            let mut row = common.to_vec();
+            row.extend([
+                csv_value(&v.host),
+                v.build.to_string(),
+                csv_value(&v.verdict),
+                v.reason.to_string(),
+            ]);
+            row.extend(tail.clone());
+            row.push(v.native_alternative.clone().unwrap_or_default());
+            row.push(csv_value(&record.parent_pid));
+            row
+        })
+        .collect()
+}
+
+/// A value the way it is serialised to JSON, without the quotes for strings.
+fn csv_value(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(serde_json::Value::Null) | Err(_) => String::new(),
+        Ok(value) => value.to_string(),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed writing output: {}", source))]
+    Io { source: std::io::Error },
+    #[snafu(display("failed serializing output: {}", source))]
+    Json { source: serde_json::Error },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+        vec![
+            Record {
+                section: Section::Executable,
+                pid: None,
+                parent_pid: None,
+                path: r"C:\Tools\a, b.exe".to_string(),
+                path_source: Some(Source::User),
+                duplicates: vec![],
+                architecture: Some(Architecture::Amd64),
+                kind: Some(ImageKind::Code),
+                sha256: Some("ab".repeat(32)),
+                hosts: vec![HostVerdict::new(
+                    &host,
+                    Compatibility {
+                        verdict: Verdict::Emulated,
+                        reason: "x64 is emulated on ARM64",
+                    },
+                )],
+                modules: vec![],
+                error: None,
+                policy: Some(Decision {
+                    allowed: false,
+                    rule: None,
+                }),
+            },
+            Record {
+                section: Section::Process,
+                pid: Some(4),
+                parent_pid: Some(0),
+                path: "System".to_string(),
+                path_source: None,
+                duplicates: vec![],
+                architecture: None,
+                kind: None,
+                sha256: None,
+                hosts: vec![],
+                modules: vec![],
+                error: Some("access denied".to_string()),
+                policy: None,
+            },
+        ]
+    }
+
+    fn write(format: Format) -> String {
+        let mut out = Vec::new();
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+        let mut writer = Writer::new(format, &mut out, vec![host], false).unwrap();
+        writer.begin(Section::Executable, "executables found in PATH");
+        writer.hidden();
+        for record in records() {
+            writer.record(record).unwrap();
+        }
+        assert_eq!(
+            writer.totals,
+            vec![Totals {
+                section: Section::Executable,
+                inspected: 2,
+                shown: 1,
+                failed: 1,
+                duplicates: 0,
+                violations: 1,
+            }]
+        );
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let out = write(Format::Ndjson);
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["schema_version"], 1);
+        assert_eq!(first["section"], "executable");
+        assert_eq!(first["architecture"], "x64");
+        assert_eq!(first["kind"], "code");
+        assert_eq!(first["hosts"][0]["host"], "arm64");
+        assert_eq!(first["hosts"][0]["verdict"], "emulated");
+        assert!(first["error"].is_null());
+        assert_eq!(first["policy"]["allowed"], false);
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["pid"], 4);
+        assert_eq!(second["error"], "access denied");
+    }
+
+    #[test]
+    fn test_json() {
+        let out: serde_json::Value = serde_json::from_str(&write(Format::Json)).unwrap();
+        assert_eq!(out.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            write(Format::Csv),
+            "schema_version,section,pid,path,architecture,kind,host,build,verdict,reason,error,\
+             policy_allowed,policy_rule,sha256,path_source,native_alternative,parent_pid\r\n\
+             1,executable,,\"C:\\Tools\\a, b.exe\",x64,code,arm64,26100,emulated,x64 is emulated on ARM64,,false,,\
+             abababababababababababababababababababababababababababababababab,user,,\r\n\
+             1,process,4,System,,,,,,,access denied,,,,,,0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_fold_duplicates() {
+        let [executable, _] = <[Record; 2]>::try_from(records()).unwrap();
+        let copy = |path: &str, allowed| Record {
+            path: path.to_string(),
+            policy: Some(Decision {
+                allowed,
+                rule: None,
+            }),
+            ..executable.clone()
+        };
+        let fold = |format| {
+            let mut out = Vec::new();
+            let mut writer = Writer::new(format, &mut out, vec![Host::current()], false).unwrap();
+            writer.begin(Section::Executable, "executables found");
+            writer.record(copy(r"C:\A\Qt6Core.dll", true)).unwrap();
+            writer.record(copy(r"C:\B\Qt6Core.dll", true)).unwrap();
+            // a copy decided otherwise by the policy stays apart
+            writer.record(copy(r"C:\C\Qt6Core.dll", false)).unwrap();
+            // as does one with a native build beside it
+            let mut beside = copy(r"C:\D\x64\Qt6Core.dll", true);
+            beside.hosts[0].native_alternative = Some(r"C:\D\arm64\Qt6Core.dll".to_string());
+            writer.record(beside).unwrap();
+            let totals = writer.finish().unwrap();
+            let folded = matches!(format, Format::Table | Format::Json);
+            assert_eq!(
+                (totals[0].shown, totals[0].duplicates),
+                (4, folded as usize)
+            );
+            String::from_utf8(out).unwrap()
+        };
+
+        let out: serde_json::Value = serde_json::from_str(&fold(Format::Json)).unwrap();
+        let records = out.as_array().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["path"], r"C:\A\Qt6Core.dll");
+        assert_eq!(records[0]["duplicates"][0], r"C:\B\Qt6Core.dll");
+        assert_eq!(records[0]["sha256"], "ab".repeat(32));
+        assert_eq!(records[1]["duplicates"].as_array().unwrap().len(), 0);
+        assert_eq!(
+            records[2]["hosts"][0]["native_alternative"],
+            r"C:\D\arm64\Qt6Core.dll"
+        );
+
+        let table = fold(Format::Table);
+        assert!(table.contains("C:\\A\\Qt6Core.dll (user PATH)     | x64"));
+        assert!(table.contains("\n| C:\\B\\Qt6Core.dll "));
+        assert!(table.contains("native alternative available at C:\\D\\arm64\\Qt6Core.dll"));
+        assert_eq!(fold(Format::Ndjson).lines().count(), 4);
+    }
+
+    #[test]
+    fn test_fold_processes() {
+        let [executable, _] = <[Record; 2]>::try_from(records()).unwrap();
+        let process = |pid| Record {
+            section: Section::Process,
+            pid: Some(pid),
+            parent_pid: Some(1),
+            path_source: None,
+            policy: None,
+            ..executable.clone()
+        };
+        let mut out = Vec::new();
+        let mut writer = Writer::new(Format::Json, &mut out, vec![Host::current()], false).unwrap();
+        writer.begin(Section::Process, "current running processes");
+        writer.record(process(10)).unwrap();
+        writer.record(process(11)).unwrap();
+        writer.end().unwrap();
+        // nor is an executable folded into a process of the same contents
+        writer.begin(Section::Executable, "executables found");
+        writer.record(executable.clone()).unwrap();
+        writer.record(executable.clone()).unwrap();
+        let totals = writer.finish().unwrap();
+        assert_eq!(totals[0].duplicates, 0);
+        assert_eq!(totals[1].duplicates, 1);
+
+        let out: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let records = out.as_array().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["pid"], 10);
+        assert_eq!(records[1]["pid"], 11);
+        assert_eq!(records[2]["section"], "executable");
+        assert_eq!(records[2]["duplicates"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_table() {
+        let out = write(Format::Table);
+        let (found, failures) = out.split_once("could not inspect:\n").unwrap();
+        let (found, violations) = found.split_once("policy violations:\n").unwrap();
+        assert!(violations.contains("default, not native"));
+        assert!(found.starts_with("executables found in PATH:\n"));
+        assert!(found.contains("emulated"));
+        assert!(failures.contains("access denied"));
+        assert!(out.ends_with(
+            "executables: 2 inspected, 1 shown, 1 could not be inspected, 1 policy violations\n"
+        ));
+    }
+
+    #[test]
+    fn test_tree() {
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+        let process = |pid, parent_pid, path: &str, verdict| Record {
+            section: Section::Process,
+            pid: Some(pid),
+            parent_pid: Some(parent_pid),
+            path: path.to_string(),
+            path_source: None,
+            duplicates: vec![],
+            architecture: Some(Architecture::Amd64),
+            kind: Some(ImageKind::Code),
+            sha256: Some("ab".repeat(32)),
+            hosts: vec![HostVerdict::new(
+                &host,
+                Compatibility {
+                    verdict,
+                    reason: "",
+                },
+            )],
+            modules: vec![],
+            error: None,
+            policy: None,
+        };
+        let write = |format| {
+            let mut out = Vec::new();
+            let mut writer = Writer::new(format, &mut out, vec![host], false)
+                .unwrap()
+                .with_tree(tree::Options {
+                    collapse_native: true,
+                });
+            writer.begin(Section::Process, "current running processes");
+            for record in [
+                process(10, 1, "launcher.exe", Verdict::Native),
+                process(11, 10, "helper.exe", Verdict::Emulated),
+                process(12, 10, "helper.exe", Verdict::Emulated),
+                process(4, 0, "services.exe", Verdict::Native),
+                process(100, 4, "svchost.exe", Verdict::Native),
+            ] {
+                writer.record(record).unwrap();
+            }
+            let totals = writer.finish().unwrap();
+            // the same contents are not folded, each process has its place in the tree
+            assert_eq!((totals[0].shown, totals[0].duplicates), (5, 0));
+            out
+        };
+
+        // JSON has no tree, but keeps the parent of each process
+        let out: serde_json::Value = serde_json::from_slice(&write(Format::Json)).unwrap();
+        let records = out.as_array().unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[2]["pid"], 12);
+        assert_eq!(records[2]["parent_pid"], 10);
+
+        let out = String::from_utf8(write(Format::Table)).unwrap();
+        assert!(out.contains("| 11  | ├─ helper.exe "));
+        assert!(out.contains("| 12  | └─ helper.exe "));
+        assert!(out.contains("services.exe (+1 native descendants)"));
+        assert!(!out.contains("svchost.exe"));
+    }
+}
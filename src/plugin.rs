@@ -0,0 +1,329 @@
+//! Scan audio plugins (VST2, VST3 and CLAP) and check which of them a DAW can load.
+//!
+//! A plugin is loaded in-process, so its binary must match the architecture of the host
+//! application. VST3 bundles may carry one binary per architecture under
+//! `Contents\<arch>-win`, in which case the host picks the folder matching itself and only
+//! ever loads the binary in there.
+
+use std::{
+    env,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{architecture::Architecture, detect, executable};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Vst2,
+    Vst3,
+    Clap,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Vst2 => "VST2",
+            Format::Vst3 => "VST3",
+            Format::Clap => "CLAP",
+        })
+    }
+}
+
+/// The architectures a plugin host, i.e. the DAW process, can be.
+pub const HOSTS: &[Architecture] = &[
+    Architecture::I386,
+    Architecture::Amd64,
+    Architecture::Arm64,
+    Architecture::Arm64Ec,
+];
+
+/// The `Contents\<arch>-win` folders a host looks for its binary in, in order.
+///
+/// ARM64X binaries serve both ARM64 and ARM64EC hosts, so both look into `arm64x-win` first,
+/// and an ARM64EC host falls back to x64 binaries.
+fn folders(host: Architecture) -> &'static [&'static str] {
+    match host {
+        Architecture::I386 => &["x86-win"],
+        Architecture::Amd64 => &["x86_64-win"],
+        Architecture::Arm64 => &["arm64x-win", "arm64-win"],
+        Architecture::Arm64Ec => &["arm64x-win", "arm64ec-win", "x86_64-win"],
+        _ => &[],
+    }
+}
+
+/// A single binary of a plugin.
+pub struct Binary {
+    /// The `Contents\<arch>-win` folder of a VST3 bundle the binary sits in, if any.
+    pub folder: Option<String>,
+    pub architecture: detect::Result<Architecture>,
+    /// Whether the binary carries CHPE metadata, see [`detect::pe::is_hybrid_executable`].
+    pub hybrid: bool,
+}
+
+impl Binary {
+    /// Whether the binary can be loaded into a host of `host` architecture.
+    ///
+    /// ARM64EC binaries carry the x64 machine type in their header, and an ARM64EC host loads
+    /// both them and plain x64 binaries. ARM64X binaries carry the ARM64 machine type, and
+    /// serve an ARM64EC host through their ARM64EC half.
+    pub fn can_load_into(&self, host: Architecture) -> bool {
+        let Ok(arch) = self.architecture else {
+            return false;
+        };
+        match host {
+            Architecture::Arm64Ec => {
+                arch == Architecture::Amd64 || (arch == Architecture::Arm64 && self.hybrid)
+            }
+            host => arch == host,
+        }
+    }
+
+    /// The machine type a binary in [`Self::folder`] is expected to have.
+    ///
+    /// Returns `None` when the binary is not inside a known architecture folder.
+    pub fn expected_architecture(&self) -> Option<Architecture> {
+        match self.folder.as_deref()? {
+            "x86-win" => Some(Architecture::I386),
+            "x86_64-win" | "arm64ec-win" => Some(Architecture::Amd64),
+            "arm64-win" | "arm64x-win" => Some(Architecture::Arm64),
+            _ => None,
+        }
+    }
+
+    /// Whether the binary sits in an architecture folder it does not belong to.
+    pub fn is_misplaced(&self) -> bool {
+        match (&self.architecture, self.expected_architecture()) {
+            (Ok(arch), Some(expected)) => *arch != expected,
+            _ => false,
+        }
+    }
+}
+
+pub struct Plugin {
+    pub format: Format,
+    /// The plugin file, or the bundle directory for VST3 bundles.
+    pub path: PathBuf,
+    pub binaries: Vec<Binary>,
+}
+
+impl Plugin {
+    /// Whether a host of `host` architecture can load this plugin.
+    ///
+    /// When the binaries sit in architecture folders, the host only loads the one in the first
+    /// of its [`folders`] present, otherwise any binary that matches will do.
+    pub fn loadable_by(&self, host: Architecture) -> bool {
+        if !self.binaries.iter().any(|b| b.folder.is_some()) {
+            return self.binaries.iter().any(|b| b.can_load_into(host));
+        }
+        folders(host)
+            .iter()
+            .find_map(|folder| {
+                self.binaries
+                    .iter()
+                    .find(|b| b.folder.as_deref() == Some(folder))
+            })
+            .is_some_and(|b| b.can_load_into(host))
+    }
+}
+
+/// The standard install locations of each plugin format.
+pub fn standard_locations() -> Vec<(Format, PathBuf)> {
+    let var = |name: &str| env::var_os(name).map(PathBuf::from);
+    let mut locations = Vec::new();
+    for common in [var("CommonProgramFiles"), var("CommonProgramFiles(x86)")]
+        .into_iter()
+        .flatten()
+    {
+        locations.push((Format::Vst3, common.join("VST3")));
+        locations.push((Format::Clap, common.join("CLAP")));
+        locations.push((Format::Vst2, common.join("VST2")));
+        locations.push((Format::Vst2, common.join("Steinberg").join("VST2")));
+    }
+    for program_files in [var("ProgramFiles"), var("ProgramFiles(x86)")]
+        .into_iter()
+        .flatten()
+    {
+        locations.push((Format::Vst2, program_files.join("VSTPlugins")));
+        locations.push((
+            Format::Vst2,
+            program_files.join("Steinberg").join("VSTPlugins"),
+        ));
+    }
+    if let Some(local) = var("LOCALAPPDATA") {
+        let common = local.join("Programs").join("Common");
+        locations.push((Format::Vst3, common.join("VST3")));
+        locations.push((Format::Clap, common.join("CLAP")));
+    }
+    locations.retain(|(_, dir)| dir.is_dir());
+    locations
+}
+
+/// Scan `root` for plugins.
+///
+/// `.vst3` and `.clap` files are always recognised, `.dll` files are only considered VST2
+/// plugins when `vst2_dlls` is set, since most directories are full of non-plugin DLLs. Any CPU
+/// .NET images are taken to run as `host`.
+pub fn scan(root: &Path, vst2_dlls: bool, host: Architecture) -> Vec<Plugin> {
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for file in executable::walk_files(root) {
+        let relative = file.strip_prefix(root).unwrap_or(&file);
+        let (format, path, folder) = if let Some((bundle, folder)) = vst3_bundle(root, relative) {
+            // only the binaries are of interest, skip resources and the DLLs they depend on
+            if !has_vst3_extension(file.as_os_str()) {
+                continue;
+            }
+            (Format::Vst3, bundle, folder)
+        } else {
+            let format = match extension_lowercase(&file).as_deref() {
+                Some("vst3") => Format::Vst3,
+                Some("clap") => Format::Clap,
+                Some("dll") if vst2_dlls => Format::Vst2,
+                _ => continue,
+            };
+            (format, file.clone(), None)
+        };
+        let architecture = detect::pe::detect_executable_architecture_file_for_host(&file, host);
+        let binary = Binary {
+            hybrid: architecture.is_ok()
+                && detect::pe::is_hybrid_executable_file(&file).unwrap_or(false),
+            architecture,
+            folder,
+        };
+        match plugins.iter_mut().find(|p| p.path == path) {
+            Some(plugin) => plugin.binaries.push(binary),
+            None => plugins.push(Plugin {
+                format,
+                path,
+                binaries: vec![binary],
+            }),
+        }
+    }
+    plugins
+}
+
+/// If `relative` is a file inside a VST3 bundle, returns the bundle directory and the
+/// architecture folder the file is in.
+fn vst3_bundle(root: &Path, relative: &Path) -> Option<(PathBuf, Option<String>)> {
+    let components = relative.components().collect::<Vec<_>>();
+    let bundle_index = components[..components.len().saturating_sub(1)]
+        .iter()
+        .position(|c| matches!(c, Component::Normal(name) if has_vst3_extension(name)))?;
+    let bundle = root.join(components[..=bundle_index].iter().collect::<PathBuf>());
+    let folder = match &components[bundle_index + 1..] {
+        [Component::Normal(contents), Component::Normal(folder), _]
+            if contents.eq_ignore_ascii_case("Contents") =>
+        {
+            Some(folder.to_string_lossy().to_lowercase())
+        }
+        _ => None,
+    };
+    Some((bundle, folder))
+}
+
+fn has_vst3_extension(name: &OsStr) -> bool {
+    extension_lowercase(Path::new(name)).as_deref() == Some("vst3")
+}
+
+fn extension_lowercase(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const PE_X64: &[u8] = include_bytes!("../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+    const PE_ARM64: &[u8] = include_bytes!("../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+    const PE_ARM64EC: &[u8] = include_bytes!("../test_assets/testbin_arm64ec-pc-windows-msvc.exe");
+
+    /// An ARM64X stand-in, the ARM64EC test binary with the ARM64 machine type.
+    fn arm64x() -> Vec<u8> {
+        let mut bin = PE_ARM64EC.to_vec();
+        let pe = u32::from_le_bytes(bin[0x3c..0x40].try_into().unwrap()) as usize;
+        bin[pe + 4..pe + 6].copy_from_slice(&0xaa64u16.to_le_bytes());
+        bin
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let contents = root.join("Synth.vst3").join("Contents");
+        for (folder, bin) in [
+            ("x86_64-win", PE_X64),
+            ("arm64ec-win", PE_ARM64EC),
+            // misplaced, an x64 binary in the ARM64 folder
+            ("arm64-win", PE_X64),
+        ] {
+            fs::create_dir_all(contents.join(folder)).unwrap();
+            fs::write(contents.join(folder).join("Synth.vst3"), bin).unwrap();
+        }
+        fs::create_dir_all(contents.join("Resources")).unwrap();
+        fs::write(contents.join("Resources").join("moduleinfo.json"), "{}").unwrap();
+        // DLLs in a bundle are not VST2 plugins of their own
+        fs::write(contents.join("Resources").join("helper.dll"), PE_X64).unwrap();
+        fs::write(contents.join("x86_64-win").join("dependency.dll"), PE_X64).unwrap();
+        // an ARM64EC host takes the plain ARM64 binary from `arm64x-win` and fails, even
+        // though it could load the x64 one
+        let contents = root.join("Chorus.vst3").join("Contents");
+        for (folder, bin) in [("arm64x-win", PE_ARM64), ("x86_64-win", PE_X64)] {
+            fs::create_dir_all(contents.join(folder)).unwrap();
+            fs::write(contents.join(folder).join("Chorus.vst3"), bin).unwrap();
+        }
+        fs::write(root.join("Reverb.clap"), PE_ARM64).unwrap();
+        fs::write(root.join("Delay.clap"), arm64x()).unwrap();
+        fs::write(root.join("Helper.dll"), PE_X64).unwrap();
+
+        let mut plugins = scan(root, false, Architecture::Arm64);
+        plugins.sort_by_key(|p| p.path.clone());
+        assert_eq!(plugins.len(), 4);
+        let mut with_vst2 = scan(root, true, Architecture::Arm64);
+        with_vst2.sort_by_key(|p| p.path.clone());
+        assert_eq!(
+            with_vst2
+                .iter()
+                .map(|p| (p.format, p.path.clone(), p.binaries.len()))
+                .collect::<Vec<_>>(),
+            [
+                (Format::Vst3, root.join("Chorus.vst3"), 2),
+                (Format::Clap, root.join("Delay.clap"), 1),
+                (Format::Vst2, root.join("Helper.dll"), 1),
+                (Format::Clap, root.join("Reverb.clap"), 1),
+                (Format::Vst3, root.join("Synth.vst3"), 3),
+            ]
+        );
+
+        let chorus = &plugins[0];
+        assert!(chorus.loadable_by(Architecture::Arm64));
+        assert!(chorus.loadable_by(Architecture::Amd64));
+        assert!(!chorus.loadable_by(Architecture::Arm64Ec));
+
+        let arm64x = &plugins[1];
+        assert!(arm64x.loadable_by(Architecture::Arm64));
+        assert!(arm64x.loadable_by(Architecture::Arm64Ec));
+        assert!(!arm64x.loadable_by(Architecture::Amd64));
+
+        let clap = &plugins[2];
+        assert_eq!(clap.format, Format::Clap);
+        assert!(clap.loadable_by(Architecture::Arm64));
+        assert!(!clap.loadable_by(Architecture::Arm64Ec));
+
+        let vst3 = &plugins[3];
+        assert_eq!(vst3.format, Format::Vst3);
+        assert_eq!(vst3.path, root.join("Synth.vst3"));
+        assert_eq!(vst3.binaries.len(), 3);
+        for binary in &vst3.binaries {
+            assert_eq!(
+                binary.is_misplaced(),
+                binary.folder.as_deref() == Some("arm64-win")
+            );
+        }
+        assert!(vst3.loadable_by(Architecture::Amd64));
+        assert!(vst3.loadable_by(Architecture::Arm64Ec));
+        assert!(!vst3.loadable_by(Architecture::Arm64));
+        assert!(!vst3.loadable_by(Architecture::I386));
+    }
+}
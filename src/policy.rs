@@ -0,0 +1,318 @@
+//! Allow and deny executables by a policy file, to use the scan as a gate in build pipelines.
+//!
+//! A policy is a TOML file with a list of rules, tried in order. The first rule all of whose
+//! conditions match an executable decides whether it is allowed. Executables matched by no
+//! rule are allowed if they are native on every host, and otherwise fall to `default`:
+//!
+//! ```toml
+//! default = "deny"
+//!
+//! [[rule]]
+//! action = "allow"
+//! path = "C:/Program Files/Legacy/**"
+//!
+//! [[rule]]
+//! action = "allow"
+//! publisher = "Contoso Ltd"
+//! architecture = "x64"
+//!
+//! [[rule]]
+//! action = "deny"
+//! sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+//! ```
+
+use std::{
+    cell::OnceCell,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::{architecture::Architecture, executable, signature};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    #[default]
+    Deny,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    default: Action,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    action: Action,
+    path: Option<String>,
+    sha256: Option<String>,
+    publisher: Option<String>,
+    architecture: Option<Architecture>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: Action,
+    path: Option<glob::Pattern>,
+    sha256: Option<String>,
+    publisher: Option<String>,
+    architecture: Option<Architecture>,
+}
+
+#[derive(Debug)]
+pub struct Policy {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+/// The outcome of a policy for one executable.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Decision {
+    pub allowed: bool,
+    /// The 1-based index of the deciding rule in the policy file, `None` when no rule matched.
+    pub rule: Option<usize>,
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).context(IoSnafu { path })?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse the policy `text`, read from `path`.
+    fn parse(text: &str, path: &Path) -> Result<Self> {
+        let file: File = toml::from_str(text).context(TomlSnafu { path })?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(|rule| {
+                Ok(Rule {
+                    action: rule.action,
+                    path: rule
+                        .path
+                        .map(|pattern| {
+                            // both separators are accepted, match against `/` only
+                            glob::Pattern::new(&pattern.replace('\\', "/"))
+                                .context(GlobSnafu { pattern, path })
+                        })
+                        .transpose()?,
+                    sha256: rule.sha256.map(|hash| hash.to_ascii_lowercase()),
+                    publisher: rule.publisher,
+                    architecture: rule.architecture,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Policy {
+            default: file.default,
+            rules,
+        })
+    }
+
+    /// Decide on the executable at `path` of `architecture`, `native` telling whether it runs
+    /// natively on every host evaluated against.
+    ///
+    /// The hash, unless already known as `sha256`, and the signature are only read when a rule
+    /// asks for them.
+    pub fn evaluate(
+        &self,
+        path: &Path,
+        architecture: Architecture,
+        native: bool,
+        sha256: Option<&str>,
+    ) -> Decision {
+        let sha256 = sha256
+            .map(|hash| OnceCell::from(Some(hash.to_string())))
+            .unwrap_or_default();
+        let publisher = OnceCell::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let matches = rule.architecture.is_none_or(|arch| arch == architecture)
+                && rule.path.as_ref().is_none_or(|pattern| {
+                    let path = path.to_string_lossy().replace('\\', "/");
+                    pattern.matches_with(
+                        &path,
+                        glob::MatchOptions {
+                            // paths are case insensitive on Windows
+                            case_sensitive: false,
+                            require_literal_separator: true,
+                            require_literal_leading_dot: false,
+                        },
+                    )
+                })
+                && rule.sha256.as_ref().is_none_or(|expected| {
+                    sha256
+                        .get_or_init(|| executable::sha256_file(path).ok())
+                        .as_ref()
+                        .is_some_and(|actual| actual == expected)
+                })
+                && rule.publisher.as_ref().is_none_or(|expected| {
+                    publisher
+                        .get_or_init(|| signature::publisher(path))
+                        .as_ref()
+                        .is_some_and(|actual| actual.eq_ignore_ascii_case(expected))
+                });
+            if matches {
+                return Decision {
+                    allowed: rule.action == Action::Allow,
+                    rule: Some(index + 1),
+                };
+            }
+        }
+        Decision {
+            allowed: native || self.default == Action::Allow,
+            rule: None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("failed reading policy {}: {}", path.display(), source))]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("invalid policy {}: {}", path.display(), source))]
+    Toml {
+        #[snafu(source(from(toml::de::Error, Box::new)))]
+        source: Box<toml::de::Error>,
+        path: PathBuf,
+    },
+    #[snafu(display("invalid path pattern `{}` in policy {}: {}", pattern, path.display(), source))]
+    Glob {
+        source: glob::PatternError,
+        pattern: String,
+        path: PathBuf,
+    },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const POLICY: &str = r#"
+        [[rule]]
+        action = "allow"
+        path = 'C:\Program Files\Legacy\**'
+
+        [[rule]]
+        action = "deny"
+        sha256 = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+
+        [[rule]]
+        action = "allow"
+        architecture = "x86"
+    "#;
+
+    #[test]
+    fn test_evaluate() {
+        let policy = Policy::parse(POLICY, Path::new("policy.toml")).unwrap();
+        let decide =
+            |path: &str, arch, native| policy.evaluate(Path::new(path), arch, native, None);
+
+        assert_eq!(
+            decide(
+                r"c:\program files\legacy\bin\tool.exe",
+                Architecture::Amd64,
+                false
+            ),
+            Decision {
+                allowed: true,
+                rule: Some(1)
+            }
+        );
+        // the glob does not cross into sibling directories
+        assert!(
+            !decide(
+                r"C:\Program Files\Legacy2\tool.exe",
+                Architecture::Amd64,
+                false
+            )
+            .allowed
+        );
+        assert_eq!(
+            decide(r"C:\Tools\old.exe", Architecture::I386, false),
+            Decision {
+                allowed: true,
+                rule: Some(3)
+            }
+        );
+        // no rule matches, native images pass and others fall to the default, deny
+        assert_eq!(
+            decide(r"C:\Tools\new.exe", Architecture::Arm64, true),
+            Decision {
+                allowed: true,
+                rule: None
+            }
+        );
+        assert_eq!(
+            decide(r"C:\Tools\new.exe", Architecture::Amd64, false),
+            Decision {
+                allowed: false,
+                rule: None
+            }
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy");
+        fs::write(&path, "abc").unwrap();
+        let decision = policy.evaluate(&path, Architecture::I386, false, None);
+        // a known hash is taken as is
+        assert_eq!(
+            policy.evaluate(&path, Architecture::I386, false, Some(&"0".repeat(64))),
+            Decision {
+                allowed: true,
+                rule: Some(3)
+            }
+        );
+        assert_eq!(
+            decision,
+            Decision {
+                allowed: false,
+                rule: Some(2)
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(
+            Policy::parse("default = \"allow\"", Path::new("policy.toml"))
+                .unwrap()
+                .rules
+                .is_empty()
+        );
+        assert!(matches!(
+            Policy::parse(
+                "[[rule]]\naction = \"allow\"\npath = \"[\"",
+                Path::new("policy.toml")
+            ),
+            Err(Error::Glob { .. })
+        ));
+        assert!(matches!(
+            Policy::parse(
+                "[[rule]]\naction = \"allow\"\narchitecture = \"sparc\"",
+                Path::new("policy.toml")
+            ),
+            Err(Error::Toml { .. })
+        ));
+        assert!(matches!(
+            Policy::parse(
+                "[[rule]]\naction = \"allow\"\nhash = \"00\"",
+                Path::new("policy.toml")
+            ),
+            Err(Error::Toml { .. })
+        ));
+    }
+}
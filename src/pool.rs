@@ -0,0 +1,256 @@
+//! Inspect files on a pool of worker threads, as scanning large trees one file at a time takes
+//! minutes.
+
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the progress line is redrawn.
+const TICK: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// The number of worker threads.
+    pub jobs: usize,
+    /// Deliver results in the order the items came in, rather than as they complete.
+    pub ordered: bool,
+    /// Report progress on stderr, if it is a terminal.
+    pub progress: bool,
+}
+
+/// How far a run got.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Summary {
+    /// Items taken from the iterator.
+    pub seen: usize,
+    /// Results passed to `deliver`.
+    pub delivered: usize,
+    /// Whether the run stopped early because `cancel` was set.
+    pub cancelled: bool,
+}
+
+/// Run `work` on each of `items` on `options.jobs` threads, and pass the results to `deliver`
+/// on the calling thread.
+///
+/// Setting `cancel` stops taking new items. Results already completed are still delivered, in
+/// order when `options.ordered` even though some items before them may be missing. An error from
+/// `deliver` stops the run and is returned.
+pub fn run<T, R, E>(
+    items: impl Iterator<Item = T> + Send,
+    options: Options,
+    cancel: &AtomicBool,
+    work: impl Fn(&T) -> R + Sync,
+    mut deliver: impl FnMut(T, R) -> Result<(), E>,
+) -> Result<Summary, E>
+where
+    T: Send,
+    R: Send,
+{
+    let seen = AtomicUsize::new(0);
+    let mut progress = Progress::new(options.progress && std::io::stderr().is_terminal());
+    let mut summary = thread::scope(|scope| {
+        // bounded, so that walking a tree does not race far ahead of inspecting it
+        let (item_tx, item_rx) = mpsc::sync_channel(options.jobs * 4);
+        let (result_tx, result_rx) = mpsc::channel();
+        let seen = &seen;
+        let work = &work;
+
+        scope.spawn(move || {
+            for (index, item) in items.enumerate() {
+                // fails once every worker has stopped
+                if cancel.load(Ordering::Relaxed) || item_tx.send((index, item)).is_err() {
+                    break;
+                }
+                seen.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        // workers own the receiver, so the feeder stops when they all have
+        let item_rx = Arc::new(Mutex::new(item_rx));
+        for _ in 0..options.jobs.max(1) {
+            let item_rx = item_rx.clone();
+            let result_tx = result_tx.clone();
+            scope.spawn(move || {
+                loop {
+                    // the lock is only held while waiting for the next item
+                    let Ok((index, item)) = item_rx.lock().unwrap().recv() else {
+                        break;
+                    };
+                    if cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let result = work(&item);
+                    // fails when delivering failed
+                    if result_tx.send((index, item, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(item_rx);
+        drop(result_tx);
+
+        let mut summary = Summary::default();
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        loop {
+            match result_rx.recv_timeout(TICK) {
+                Ok((index, item, result)) => {
+                    progress.clear();
+                    if options.ordered {
+                        pending.insert(index, (item, result));
+                        while let Some((item, result)) = pending.remove(&next) {
+                            deliver(item, result)?;
+                            summary.delivered += 1;
+                            next += 1;
+                        }
+                    } else {
+                        deliver(item, result)?;
+                        summary.delivered += 1;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            progress.update(
+                seen.load(Ordering::Relaxed),
+                summary.delivered + pending.len(),
+            );
+        }
+        // only left when cancelled, the items in the gaps were dropped by the workers
+        progress.clear();
+        for (item, result) in pending.into_values() {
+            deliver(item, result)?;
+            summary.delivered += 1;
+        }
+        Ok(summary)
+    })?;
+    summary.seen = seen.into_inner();
+    summary.cancelled = cancel.load(Ordering::Relaxed);
+    Ok(summary)
+}
+
+/// A single line on stderr, redrawn in place.
+struct Progress {
+    enabled: bool,
+    start: Instant,
+    last: Option<Instant>,
+    /// The width of the line drawn, 0 when there is none.
+    width: usize,
+}
+
+impl Progress {
+    fn new(enabled: bool) -> Self {
+        Progress {
+            enabled,
+            start: Instant::now(),
+            last: None,
+            width: 0,
+        }
+    }
+
+    fn update(&mut self, seen: usize, inspected: usize) {
+        if !self.enabled || self.last.is_some_and(|last| last.elapsed() < TICK) {
+            return;
+        }
+        self.last = Some(Instant::now());
+        let rate = inspected as f64 / self.start.elapsed().as_secs_f64().max(0.001);
+        let line = format!("{seen} files found, {inspected} inspected, {rate:.0} per second");
+        // pad over a longer previous line rather than relying on terminal escapes
+        let _ = write!(std::io::stderr(), "\r{line:<width$}", width = self.width);
+        self.width = self.width.max(line.len());
+    }
+
+    /// Remove the line, so that results printed to the same terminal start at the margin.
+    fn clear(&mut self) {
+        if self.width > 0 {
+            let _ = write!(std::io::stderr(), "\r{:width$}\r", "", width = self.width);
+            self.width = 0;
+            // redraw at the next update rather than waiting for the next tick
+            self.last = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(ordered: bool) -> Options {
+        Options {
+            jobs: 4,
+            ordered,
+            progress: false,
+        }
+    }
+
+    /// Later items finish first, to shake out the ordering.
+    fn slow(item: &u64) -> u64 {
+        thread::sleep(Duration::from_millis(20 - item % 20));
+        item * 2
+    }
+
+    #[test]
+    fn test_ordered() {
+        let cancel = AtomicBool::new(false);
+        let mut results = vec![];
+        let summary = run(0..50u64, options(true), &cancel, slow, |item, result| {
+            results.push((item, result));
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(results, (0..50).map(|i| (i, i * 2)).collect::<Vec<_>>());
+        assert_eq!(
+            summary,
+            Summary {
+                seen: 50,
+                delivered: 50,
+                cancelled: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_unordered() {
+        let cancel = AtomicBool::new(false);
+        let mut results = vec![];
+        run(0..50u64, options(false), &cancel, slow, |item, _| {
+            results.push(item);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        results.sort();
+        assert_eq!(results, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cancel_and_error() {
+        let cancel = AtomicBool::new(false);
+        let mut results = vec![];
+        let summary = run(0.., options(true), &cancel, slow, |item, _| {
+            if item == 10 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            results.push(item);
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert!(summary.cancelled);
+        assert_eq!(summary.delivered, results.len());
+        // partial, but still in order
+        assert!(results.len() > 10 && results.is_sorted());
+
+        let cancel = AtomicBool::new(false);
+        let error = run(0.., options(false), &cancel, slow, |item, _| {
+            if item > 10 { Err(item) } else { Ok(()) }
+        });
+        assert!(error.unwrap_err() > 10);
+    }
+}
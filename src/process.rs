@@ -1,20 +1,211 @@
-use snafu::{ResultExt, Snafu};
-use windows::Win32::{
-    Foundation::{ERROR_NO_MORE_FILES, HANDLE, WIN32_ERROR},
-    System::Diagnostics::ToolHelp::{
-        CreateToolhelp32Snapshot, PROCESSENTRY32W, Process32FirstW, Process32NextW,
-        TH32CS_SNAPPROCESS,
+//! Running processes, through a [`Backend`] so that what is made of them can be tested with a
+//! fake one, and off Windows.
+//!
+//! A process is inspected from the file it runs where its full image path can be queried, as
+//! executables are, which tells apart installs of the same name, and together with what the
+//! system reports tells ARM64EC processes apart from x64 ones.
+//!
+//! The modules a process loads need not match it: ARM64EC and x64 processes load x64 plugins,
+//! and x86 processes load CHPE modules with ARM64 code. [`foreign_modules`] finds those.
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+use snafu::Snafu;
+
+use crate::{
+    architecture::Architecture,
+    cache::{self, Cache},
+    detect::{
+        self,
+        pe::{self, ImageInfo, ImageKind},
+        process::{Machines, MainImage},
     },
 };
 
 pub struct Process {
     pub pid: u32,
-    pub exe_path: String,
+    /// The PID of the process that started it, which may have exited and had its PID reused.
+    pub parent_pid: u32,
+    /// The file name of the executable, e.g. `chrome.exe`.
+    pub name: String,
+}
+
+/// Where processes and what the system knows of them come from.
+pub trait Backend {
+    /// A snapshot of the running processes.
+    fn processes(&self) -> Result<Vec<Process>>;
+    /// The full path of the image the process runs, `None` when it cannot be queried, as for
+    /// protected processes and those that already exited.
+    fn image_path(&self, pid: u32) -> Option<PathBuf>;
+    /// The machines the system reports for the process.
+    fn machines(&self, pid: u32) -> detect::Result<Machines>;
+    /// The paths of the images loaded into the process, other than its own.
+    fn modules(&self, pid: u32) -> Result<Vec<PathBuf>>;
+}
+
+/// What is known of a running process.
+pub struct Inspection {
+    pub pid: u32,
+    pub parent_pid: u32,
+    /// The full image path, or the file name when it cannot be queried.
+    pub path: String,
+    /// `None` when only the file name is known.
+    pub image_path: Option<PathBuf>,
+    /// The image as seen by each host. The architecture is the one the process runs as, decided
+    /// from the machines the system reports and the image file, or from the file alone when the
+    /// system reports nothing. The kind is from the file, plain code when it cannot be read.
+    pub infos: detect::Result<Vec<ImageInfo>>,
+    /// The SHA-256 of the image file, when asked for and it could be read.
+    pub sha256: Option<String>,
 }
-impl From<PROCESSENTRY32W> for Process {
-    fn from(pe32w: PROCESSENTRY32W) -> Self {
+
+/// Inspect `process` as seen by each of `hosts`, detecting its image file through `cache` if
+/// there is one, and hashing it if `hash` is set.
+pub fn inspect(
+    backend: &impl Backend,
+    process: Process,
+    hosts: &[Architecture],
+    cache: Option<&Cache>,
+    hash: bool,
+) -> Inspection {
+    let image_path = backend.image_path(process.pid);
+    let detected = image_path
+        .as_deref()
+        .and_then(|path| cache::detect(cache, path, hosts, hash).ok());
+    let image = image_path
+        .as_deref()
+        .zip(detected.as_ref())
+        .map(|(path, detected)| {
+            let architecture = detected.infos[0].architecture;
+            MainImage {
+                architecture,
+                // only x64 images may be ARM64EC
+                hybrid: architecture == Architecture::Amd64
+                    && pe::is_hybrid_executable_file(path).unwrap_or(false),
+            }
+        });
+    let sha256 = detected
+        .as_ref()
+        .and_then(|detected| detected.sha256.clone());
+    let infos = match (backend.machines(process.pid), detected) {
+        (Ok(machines), detected) => {
+            let info = ImageInfo {
+                architecture: detect::process::decide(&machines, image.as_ref()),
+                kind: detected.map_or(ImageKind::Code, |detected| detected.infos[0].kind),
+            };
+            Ok(vec![info; hosts.len()])
+        }
+        (Err(_), Some(detected)) => Ok(detected.infos),
+        (Err(e), None) => Err(e),
+    };
+    Inspection {
+        pid: process.pid,
+        parent_pid: process.parent_pid,
+        path: image_path
+            .as_ref()
+            .map_or(process.name, |path| path.display().to_string()),
+        image_path,
+        infos,
+        sha256,
+    }
+}
+
+/// A module loaded into a process whose code does not match the process.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Module {
+    pub path: PathBuf,
+    pub architecture: Architecture,
+    pub reason: &'static str,
+}
+
+/// The modules loaded into process `pid`, running as `architecture`, whose code does not match
+/// it, detecting them through `cache` if there is one.
+///
+/// Modules are detected as seen by the process, so that AnyCPU assemblies take its
+/// architecture. Modules that cannot be read are left out.
+pub fn foreign_modules(
+    backend: &impl Backend,
+    pid: u32,
+    architecture: Architecture,
+    cache: Option<&Cache>,
+) -> Result<Vec<Module>> {
+    let mut foreign = vec![];
+    for path in backend.modules(pid)? {
+        let Ok(detected) = cache::detect(cache, &path, &[architecture], false) else {
+            continue;
+        };
+        let info = detected.infos[0];
+        let hybrid = || pe::is_hybrid_executable_file(&path).unwrap_or(false);
+        if let Some(reason) = mismatch(architecture, &info, hybrid) {
+            foreign.push(Module {
+                path,
+                architecture: info.architecture,
+                reason,
+            });
+        }
+    }
+    Ok(foreign)
+}
+
+/// Why a module detected as `module` does not match a process running as `process`, `None` if
+/// it does.
+///
+/// `hybrid` tells whether the module carries CHPE metadata, it is only asked where that makes a
+/// difference, as it means reading the file again.
+pub fn mismatch(
+    process: Architecture,
+    module: &ImageInfo,
+    hybrid: impl FnOnce() -> bool,
+) -> Option<&'static str> {
+    use Architecture::*;
+
+    if module.is_architecture_neutral() {
+        return None;
+    }
+    match (process, module.architecture) {
+        (I386, I386) => hybrid().then_some("CHPE module, its ARM64 code runs natively"),
+        // ARM64EC modules carry the x64 machine type
+        (Amd64, Amd64) => hybrid().then_some("ARM64EC module, runs natively in an x64 process"),
+        (Arm64Ec, Amd64) => (!hybrid()).then_some("x64 module, emulated in an ARM64EC process"),
+        // ARM64X modules, which System32 is made of on ARM64, serve x64 and ARM64EC processes
+        // through their ARM64EC half
+        (Amd64 | Arm64Ec, Arm64) => (!hybrid()).then_some("ARM64 module without ARM64EC code"),
+        (process, module) if process == module => None,
+        _ => Some("not of the architecture of the process"),
+    }
+}
+
+/// The processes of this system, from a Toolhelp snapshot.
+#[cfg(windows)]
+pub struct System;
+
+#[cfg(windows)]
+impl Backend for System {
+    fn processes(&self) -> Result<Vec<Process>> {
+        enumrate_running_processes()?.collect()
+    }
+
+    fn image_path(&self, pid: u32) -> Option<PathBuf> {
+        query_image_path(pid).ok()
+    }
+
+    fn machines(&self, pid: u32) -> detect::Result<Machines> {
+        detect::process::detect_process_machines_by_pid(pid)
+    }
+
+    fn modules(&self, pid: u32) -> Result<Vec<PathBuf>> {
+        enumrate_modules(pid)
+    }
+}
+
+#[cfg(windows)]
+impl From<windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32W> for Process {
+    fn from(pe32w: windows::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32W) -> Self {
         let pid = pe32w.th32ProcessID;
-        let exe_path = String::from_utf16_lossy({
+        let parent_pid = pe32w.th32ParentProcessID;
+        let name = String::from_utf16_lossy({
             let end = pe32w
                 .szExeFile
                 .iter()
@@ -22,11 +213,25 @@ impl From<PROCESSENTRY32W> for Process {
                 .unwrap_or(pe32w.szExeFile.len());
             &pe32w.szExeFile[..end]
         });
-        Process { pid, exe_path }
+        Process {
+            pid,
+            parent_pid,
+            name,
+        }
     }
 }
 
+#[cfg(windows)]
 pub fn enumrate_running_processes() -> Result<impl Iterator<Item = Result<Process>>> {
+    use snafu::ResultExt;
+    use windows::Win32::{
+        Foundation::{ERROR_NO_MORE_FILES, HANDLE, WIN32_ERROR},
+        System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, PROCESSENTRY32W, Process32FirstW, Process32NextW,
+            TH32CS_SNAPPROCESS,
+        },
+    };
+
     let process_snap =
         unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0).context(WindowsSnafu)? };
     struct Iter {
@@ -49,10 +254,10 @@ pub fn enumrate_running_processes() -> Result<impl Iterator<Item = Result<Proces
                 }
             } else {
                 unsafe {
-                    if let Err(e) = Process32NextW(self.process_snap, &mut self.pe32w) {
-                        if let Some(ERROR_NO_MORE_FILES) = WIN32_ERROR::from_error(&e) {
-                            return None;
-                        }
+                    if let Err(e) = Process32NextW(self.process_snap, &mut self.pe32w)
+                        && let Some(ERROR_NO_MORE_FILES) = WIN32_ERROR::from_error(&e)
+                    {
+                        return None;
                     }
                 }
             }
@@ -65,8 +270,94 @@ pub fn enumrate_running_processes() -> Result<impl Iterator<Item = Result<Proces
     })
 }
 
+/// The full Win32 path of the image of process `pid`.
+#[cfg(windows)]
+fn query_image_path(pid: u32) -> Result<PathBuf> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    use snafu::ResultExt;
+    use windows::{
+        Win32::{
+            Foundation::CloseHandle,
+            System::Threading::{
+                OpenProcess, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+                QueryFullProcessImageNameW,
+            },
+        },
+        core::PWSTR,
+    };
+
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }
+        .context(WindowsSnafu)?;
+    // long paths are not limited to MAX_PATH, this is the longest the system allows
+    let mut buffer = vec![0u16; 32768];
+    let mut size = buffer.len() as u32;
+    let queried = unsafe {
+        QueryFullProcessImageNameW(
+            handle,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )
+    };
+    let _ = unsafe { CloseHandle(handle) };
+    queried.context(WindowsSnafu)?;
+    Ok(OsString::from_wide(&buffer[..size as usize]).into())
+}
+
+/// The paths of the modules loaded into process `pid`, other than its own image, from a
+/// Toolhelp snapshot.
+#[cfg(windows)]
+fn enumrate_modules(pid: u32) -> Result<Vec<PathBuf>> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    use snafu::ResultExt;
+    use windows::Win32::{
+        Foundation::{CloseHandle, ERROR_BAD_LENGTH, ERROR_NO_MORE_FILES, WIN32_ERROR},
+        System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, MODULEENTRY32W, Module32FirstW, Module32NextW,
+            TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32,
+        },
+    };
+
+    let mut attempts = 0;
+    let snapshot = loop {
+        match unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, pid) } {
+            // the process was loading or unloading modules, which is worth another try
+            Err(e) if attempts < 3 && WIN32_ERROR::from_error(&e) == Some(ERROR_BAD_LENGTH) => {
+                attempts += 1;
+            }
+            snapshot => break snapshot.context(WindowsSnafu)?,
+        }
+    };
+    let mut entry = MODULEENTRY32W {
+        dwSize: std::mem::size_of::<MODULEENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut modules = vec![];
+    let mut next = unsafe { Module32FirstW(snapshot, &mut entry) };
+    while next.is_ok() {
+        let end = entry
+            .szExePath
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(entry.szExePath.len());
+        modules.push(OsString::from_wide(&entry.szExePath[..end]).into());
+        next = unsafe { Module32NextW(snapshot, &mut entry) };
+    }
+    let _ = unsafe { CloseHandle(snapshot) };
+    match next {
+        Err(e) if WIN32_ERROR::from_error(&e) != Some(ERROR_NO_MORE_FILES) => {
+            Err(e).context(WindowsSnafu)
+        }
+        // the first module is the image of the process itself
+        _ => Ok(modules.into_iter().skip(1).collect()),
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum Error {
+    #[cfg(windows)]
     #[snafu(display("windows api error: {}", source))]
     Windows { source: windows::core::Error },
 }
@@ -74,16 +365,228 @@ type Result<T> = std::result::Result<T, Error>;
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use super::*;
 
+    /// A process as the system would report it.
+    struct FakeProcess {
+        pid: u32,
+        name: &'static str,
+        image_path: Option<PathBuf>,
+        machines: Option<Machines>,
+        modules: Vec<PathBuf>,
+    }
+
+    fn fake(
+        pid: u32,
+        name: &'static str,
+        image_path: Option<PathBuf>,
+        machines: Option<Machines>,
+    ) -> FakeProcess {
+        FakeProcess {
+            pid,
+            name,
+            image_path,
+            machines,
+            modules: vec![],
+        }
+    }
+
+    /// The machines reported on ARM64, `process` only from Windows 11 on.
+    fn on_arm64(wow64: Option<Architecture>, process: Option<Architecture>) -> Option<Machines> {
+        Some(Machines {
+            wow64,
+            native: Architecture::Arm64,
+            process,
+        })
+    }
+
+    struct Fake(Vec<FakeProcess>);
+
+    impl Fake {
+        fn find(&self, pid: u32) -> Option<&FakeProcess> {
+            self.0.iter().find(|p| p.pid == pid)
+        }
+    }
+
+    impl Backend for Fake {
+        fn processes(&self) -> Result<Vec<Process>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|p| Process {
+                    pid: p.pid,
+                    parent_pid: 1,
+                    name: p.name.to_string(),
+                })
+                .collect())
+        }
+
+        fn image_path(&self, pid: u32) -> Option<PathBuf> {
+            self.find(pid)?.image_path.clone()
+        }
+
+        fn machines(&self, pid: u32) -> detect::Result<Machines> {
+            self.find(pid)
+                .and_then(|p| p.machines)
+                .ok_or(detect::Error::Empty {
+                    msg: "access denied".to_string(),
+                })
+        }
+
+        fn modules(&self, pid: u32) -> Result<Vec<PathBuf>> {
+            Ok(self
+                .find(pid)
+                .map(|p| p.modules.clone())
+                .unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_inspect() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+        let x64 = assets.join("testbin_x86_64-pc-windows-msvc.exe");
+        let arm64ec = assets.join("testbin_arm64ec-pc-windows-msvc.exe");
+        let fake = Fake(vec![
+            // x64 on ARM64 is not WOW64, before Windows 11 only the image tells it is not native
+            fake(1, "tool.exe", Some(x64.clone()), on_arm64(None, None)),
+            fake(4, "System", None, on_arm64(None, Some(Architecture::Arm64))),
+            fake(
+                5,
+                "script.exe",
+                Some(assets.join("missing.exe")),
+                on_arm64(Some(Architecture::I386), Some(Architecture::I386)),
+            ),
+            fake(6, "protected.exe", None, None),
+            fake(
+                7,
+                "app.exe",
+                Some(arm64ec),
+                on_arm64(None, Some(Architecture::Amd64)),
+            ),
+            // the image alone when the system reports nothing
+            fake(8, "elevated.exe", Some(x64.clone()), None),
+        ]);
+        let hosts = [Architecture::Arm64, Architecture::Amd64];
+        let inspections = fake
+            .processes()
+            .unwrap()
+            .into_iter()
+            .map(|process| inspect(&fake, process, &hosts, None, true))
+            .collect::<Vec<_>>();
+
+        assert_eq!(inspections[0].path, x64.display().to_string());
+        assert_eq!(inspections[0].image_path.as_ref(), Some(&x64));
+        let infos = inspections[0].infos.as_ref().unwrap();
+        assert!(infos.iter().all(|i| i.architecture == Architecture::Amd64));
+        assert!(inspections[0].sha256.is_some());
+
+        // without a readable image, the name and the architecture the system reports are left
+        assert_eq!(inspections[1].path, "System");
+        assert_eq!(
+            inspections[1].infos.as_ref().unwrap(),
+            &[ImageInfo {
+                architecture: Architecture::Arm64,
+                kind: ImageKind::Code,
+            }; 2]
+        );
+        assert_eq!(inspections[1].sha256, None);
+        assert_eq!(
+            inspections[2].infos.as_ref().unwrap()[0].architecture,
+            Architecture::I386
+        );
+        assert!(inspections[3].infos.is_err());
+        assert_eq!(
+            inspections[4].infos.as_ref().unwrap()[0].architecture,
+            Architecture::Arm64Ec
+        );
+        assert_eq!(
+            inspections[5].infos.as_ref().unwrap()[1].architecture,
+            Architecture::Amd64
+        );
+    }
+
+    #[test]
+    fn test_foreign_modules() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+        let asset = |name: &str| assets.join(format!("testbin_{name}-pc-windows-msvc.exe"));
+        let mut process = fake(8, "host.exe", None, None);
+        process.modules = vec![
+            asset("x86_64"),
+            asset("arm64ec"),
+            asset("aarch64"),
+            assets.join("missing.dll"),
+        ];
+        let fake = Fake(vec![process]);
+        let foreign = |architecture| {
+            foreign_modules(&fake, 8, architecture, None)
+                .unwrap()
+                .into_iter()
+                .map(|module| module.path)
+                .collect::<Vec<_>>()
+        };
+
+        // the ARM64EC module runs natively, the plain ARM64 one cannot serve x64 code
+        assert_eq!(
+            foreign(Architecture::Amd64),
+            [asset("arm64ec"), asset("aarch64")]
+        );
+        assert_eq!(
+            foreign(Architecture::Arm64Ec),
+            [asset("x86_64"), asset("aarch64")]
+        );
+        assert_eq!(
+            foreign(Architecture::Arm64),
+            [asset("x86_64"), asset("arm64ec")]
+        );
+    }
+
+    #[test]
+    fn test_mismatch() {
+        use Architecture::*;
+
+        let code = |architecture| ImageInfo {
+            architecture,
+            kind: ImageKind::Code,
+        };
+        let resources = ImageInfo {
+            architecture: I386,
+            kind: ImageKind::ResourceOnly,
+        };
+        for (process, module, hybrid, mismatched) in [
+            (I386, code(I386), false, false),
+            (I386, code(I386), true, true),
+            (Amd64, code(Amd64), false, false),
+            (Amd64, code(Amd64), true, true),
+            (Amd64, code(Arm64), true, false),
+            (Amd64, code(Arm64), false, true),
+            (Arm64Ec, code(Amd64), false, true),
+            (Arm64Ec, code(Amd64), true, false),
+            (Arm64Ec, code(Arm64), true, false),
+            (Arm64, code(Arm64), true, false),
+            (Arm64, code(Amd64), false, true),
+            (Arm64, resources, false, false),
+        ] {
+            assert_eq!(
+                mismatch(process, &module, || hybrid).is_some(),
+                mismatched,
+                "{module:?} in {process} with hybrid {hybrid}"
+            );
+        }
+    }
+
+    #[cfg(windows)]
     #[test]
     fn test_enumrate_running_processes() {
         let processes = enumrate_running_processes().unwrap();
         for process in processes {
             let process = process.unwrap();
             println!(
-                "Process ID: {}, Executable: {}",
-                process.pid, process.exe_path
+                "Process ID: {}, Executable: {}, Path: {:?}",
+                process.pid,
+                process.name,
+                System.image_path(process.pid)
             );
         }
     }
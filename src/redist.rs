@@ -0,0 +1,234 @@
+//! Find the runtime redistributables that non-native executables need, but that are not
+//! installed for their architecture.
+//!
+//! Emulated applications load the runtime DLLs of their own architecture, so an ARM64 machine
+//! with only the ARM64 Visual C++ runtime installed still lacks it for every x64 application.
+
+use std::path::Path;
+
+use crate::{architecture::Architecture, dependency::Resolver, detect};
+
+pub struct Redistributable {
+    pub name: &'static str,
+    /// Prefixes of the lowercased names of the DLLs this redistributable installs.
+    dll_prefixes: &'static [&'static str],
+}
+
+pub const REDISTRIBUTABLES: &[Redistributable] = &[
+    Redistributable {
+        name: "Microsoft Visual C++ 2015-2022 Redistributable",
+        dll_prefixes: &[
+            "vcruntime140",
+            "msvcp140",
+            "concrt140",
+            "vccorlib140",
+            "vcomp140",
+            "vcamp140",
+            "mfc140",
+            "mfcm140",
+        ],
+    },
+    Redistributable {
+        name: "Microsoft Visual C++ 2013 Redistributable",
+        dll_prefixes: &[
+            "msvcr120",
+            "msvcp120",
+            "vccorlib120",
+            "vcomp120",
+            "vcamp120",
+            "mfc120",
+            "mfcm120",
+        ],
+    },
+    Redistributable {
+        name: "Microsoft Visual C++ 2012 Redistributable",
+        dll_prefixes: &[
+            "msvcr110",
+            "msvcp110",
+            "vccorlib110",
+            "vcomp110",
+            "vcamp110",
+            "mfc110",
+            "mfcm110",
+        ],
+    },
+    Redistributable {
+        name: "Microsoft Visual C++ 2010 Redistributable",
+        dll_prefixes: &["msvcr100", "msvcp100", "vcomp100", "mfc100", "mfcm100"],
+    },
+    Redistributable {
+        name: "Microsoft Visual C++ 2008 Redistributable",
+        dll_prefixes: &["msvcr90", "msvcp90", "vcomp90", "mfc90", "mfcm90"],
+    },
+    Redistributable {
+        name: "Microsoft Visual C++ 2005 Redistributable",
+        dll_prefixes: &["msvcr80", "msvcp80", "vcomp.", "mfc80", "mfcm80"],
+    },
+    Redistributable {
+        name: "Universal C Runtime",
+        dll_prefixes: &["ucrtbase", "api-ms-win-crt-"],
+    },
+    Redistributable {
+        name: "DirectX End-User Runtime (June 2010)",
+        dll_prefixes: &[
+            "d3dx9_",
+            "d3dx10",
+            "d3dx11",
+            "d3dcompiler_3",
+            "d3dcompiler_40",
+            "d3dcompiler_41",
+            "d3dcompiler_42",
+            "d3dcompiler_43",
+            "xinput1_1",
+            "xinput1_2",
+            "xinput1_3",
+            "xaudio2_0",
+            "xaudio2_1",
+            "xaudio2_2",
+            "xaudio2_3",
+            "xaudio2_4",
+            "xaudio2_5",
+            "xaudio2_6",
+            "xaudio2_7",
+            "x3daudio1_",
+            "xapofx1_",
+        ],
+    },
+];
+
+impl Redistributable {
+    /// The redistributable that installs `dll`, if it is a known runtime DLL.
+    pub fn for_dll(dll: &str) -> Option<&'static Redistributable> {
+        let dll = dll.to_ascii_lowercase();
+        REDISTRIBUTABLES
+            .iter()
+            .find(|r| r.dll_prefixes.iter().any(|prefix| dll.starts_with(prefix)))
+    }
+}
+
+/// A runtime DLL imported by an executable, with no copy of the right architecture available.
+pub struct Gap {
+    pub dll: String,
+    pub redistributable: &'static Redistributable,
+}
+
+/// Find the runtime DLLs imported by `exe`, of architecture `arch`, that are neither next to it
+/// nor in the system directory of its architecture.
+pub fn find_gaps(exe: &Path, arch: Architecture, resolver: &Resolver) -> detect::Result<Vec<Gap>> {
+    let app_dir = exe.parent().unwrap_or(Path::new(""));
+    let mut gaps = Vec::new();
+    for import in detect::imports::read_imports_file(exe)? {
+        let Some(redistributable) = Redistributable::for_dll(&import.dll) else {
+            continue;
+        };
+        // the CRT API sets are forwarded to ucrtbase by the loader
+        let file = if import
+            .dll
+            .to_ascii_lowercase()
+            .starts_with("api-ms-win-crt-")
+        {
+            "ucrtbase.dll"
+        } else {
+            &import.dll
+        };
+        let available = [app_dir.to_path_buf(), resolver.system_dir(arch)]
+            .into_iter()
+            .map(|dir| dir.join(file))
+            .filter(|path| path.is_file())
+            .any(|path| is_available(resolver, arch, &path));
+        if !available && !gaps.iter().any(|g: &Gap| g.dll.eq_ignore_ascii_case(file)) {
+            gaps.push(Gap {
+                dll: file.to_string(),
+                redistributable,
+            });
+        }
+    }
+    Ok(gaps)
+}
+
+fn is_available(resolver: &Resolver, arch: Architecture, path: &Path) -> bool {
+    matches!(
+        detect::pe::detect_executable_architecture_file_for_host(path, resolver.host()),
+        Ok(dll_arch) if resolver.is_loadable(arch, path, dll_arch)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const PE_X64: &[u8] = include_bytes!("../test_assets/testbin_x86_64-pc-windows-msvc.exe");
+    const PE_ARM64: &[u8] = include_bytes!("../test_assets/testbin_aarch64-pc-windows-msvc.exe");
+
+    /// The x64 test binary importing the Visual C++ runtime instead of kernel32, whose name
+    /// is just as long.
+    fn importing_msvcp140() -> Vec<u8> {
+        let mut bin = PE_X64.to_vec();
+        let at = bin
+            .windows(12)
+            .position(|name| name.eq_ignore_ascii_case(b"kernel32.dll"))
+            .unwrap();
+        bin[at..at + 12].copy_from_slice(b"msvcp140.dll");
+        bin
+    }
+
+    #[test]
+    fn test_for_dll() {
+        let name = |dll| Redistributable::for_dll(dll).map(|r| r.name);
+        assert_eq!(
+            name("VCRUNTIME140_1.dll"),
+            Some("Microsoft Visual C++ 2015-2022 Redistributable")
+        );
+        assert_eq!(
+            name("MSVCP120.dll"),
+            Some("Microsoft Visual C++ 2013 Redistributable")
+        );
+        assert_eq!(
+            name("api-ms-win-crt-runtime-l1-1-0.dll"),
+            Some("Universal C Runtime")
+        );
+        assert_eq!(
+            name("d3dx9_43.dll"),
+            Some("DirectX End-User Runtime (June 2010)")
+        );
+        // shipped with Windows itself
+        assert_eq!(name("xaudio2_9.dll"), None);
+        assert_eq!(name("kernel32.dll"), None);
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let app = root.join("app");
+        let system32 = root.join("Windows").join("System32");
+        fs::create_dir_all(&app).unwrap();
+        fs::create_dir_all(&system32).unwrap();
+        let resolver = Resolver::new(root.to_path_buf(), Architecture::Arm64, vec![]);
+        let gaps = || {
+            find_gaps(&app.join("app.exe"), Architecture::Amd64, &resolver)
+                .unwrap()
+                .into_iter()
+                .map(|gap| (gap.dll, gap.redistributable.name))
+                .collect::<Vec<_>>()
+        };
+
+        // the test binary only imports kernel32, which is not part of any redistributable
+        fs::write(app.join("app.exe"), PE_X64).unwrap();
+        assert!(gaps().is_empty());
+
+        fs::write(app.join("app.exe"), importing_msvcp140()).unwrap();
+        let missing = vec![(
+            "msvcp140.dll".to_string(),
+            "Microsoft Visual C++ 2015-2022 Redistributable",
+        )];
+        assert_eq!(gaps(), missing);
+        // only the ARM64 runtime is installed, which x64 processes cannot load
+        fs::write(system32.join("msvcp140.dll"), PE_ARM64).unwrap();
+        assert_eq!(gaps(), missing);
+        fs::write(system32.join("msvcp140.dll"), PE_X64).unwrap();
+        assert!(gaps().is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+//! Parse `PATH` the way the system does, rather than splitting it on `;`.
+//!
+//! On Windows entries are separated by `;`, may be quoted to contain one, and may carry
+//! `%VAR%` references left unexpanded by `REG_EXPAND_SZ` values. Elsewhere entries are separated
+//! by `:` and taken literally. Entries are normalised and de-duplicated, comparing
+//! case-insensitively on Windows.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use serde::Serialize;
+use snafu::Snafu;
+
+/// Where a `PATH` entry came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// The environment of this process.
+    Process,
+    /// The machine-wide `Path` in the registry.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Machine,
+    /// The `Path` of the current user in the registry.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    User,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Source::Process => "process",
+            Source::Machine => "machine",
+            Source::User => "user",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub dir: PathBuf,
+    pub source: Source,
+}
+
+/// The rules for reading a `PATH` value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Syntax {
+    pub separator: char,
+    /// Whether `"` quotes a separator and `%VAR%` is expanded.
+    pub windows: bool,
+}
+
+impl Syntax {
+    pub const WINDOWS: Syntax = Syntax {
+        separator: ';',
+        windows: true,
+    };
+    pub const UNIX: Syntax = Syntax {
+        separator: ':',
+        windows: false,
+    };
+    pub const NATIVE: Syntax = if cfg!(windows) {
+        Syntax::WINDOWS
+    } else {
+        Syntax::UNIX
+    };
+
+    /// The directories listed in `value`, expanded with `lookup` and normalised, in order.
+    ///
+    /// Empty entries are dropped, on Unix they would stand for the current directory, which
+    /// is not what anyone scanning `PATH` wants to see.
+    pub fn parse(self, value: &str, lookup: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+        self.split(value)
+            .into_iter()
+            .map(|entry| {
+                if self.windows {
+                    self.normalise(&expand(&entry, &lookup))
+                } else {
+                    self.normalise(&entry)
+                }
+            })
+            .filter(|entry| !entry.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    fn split(self, value: &str) -> Vec<String> {
+        let mut entries = vec![];
+        let mut entry = String::new();
+        let mut quoted = false;
+        for c in value.chars() {
+            match c {
+                '"' if self.windows => quoted = !quoted,
+                c if c == self.separator && !quoted => entries.push(std::mem::take(&mut entry)),
+                c => entry.push(c),
+            }
+        }
+        entries.push(entry);
+        entries
+    }
+
+    /// Trim whitespace and trailing separators, short of the root.
+    fn normalise(self, entry: &str) -> String {
+        let entry = entry.trim();
+        let entry = if self.windows {
+            entry.replace('/', "\\")
+        } else {
+            entry.to_string()
+        };
+        let separator = if self.windows { '\\' } else { '/' };
+        let trimmed = entry.trim_end_matches(separator);
+        // keep `/`, `\`, `C:\` and the `\\` that starts UNC paths
+        if trimmed.is_empty() {
+            let keep = if self.windows { 2 } else { 1 };
+            entry[..entry.len().min(keep)].to_string()
+        } else if self.windows && trimmed.ends_with(':') {
+            entry[..trimmed.len() + 1].to_string()
+        } else {
+            trimmed.to_string()
+        }
+    }
+
+    /// The key under which two entries are the same directory.
+    fn key(self, dir: &std::path::Path) -> String {
+        let dir = dir.to_string_lossy();
+        if self.windows {
+            dir.to_lowercase()
+        } else {
+            dir.into_owned()
+        }
+    }
+}
+
+/// Expand `%VAR%` references in `entry`, leaving unknown ones as they are, as `cmd` does.
+fn expand(entry: &str, lookup: &impl Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = entry;
+    while let Some(start) = rest.find('%') {
+        let Some(len) = rest[start + 1..].find('%') else {
+            break;
+        };
+        let name = &rest[start + 1..start + 1 + len];
+        expanded.push_str(&rest[..start]);
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => {
+                expanded.push_str(&value);
+                rest = &rest[start + len + 2..];
+            }
+            None => {
+                // the closing `%` may open the next reference
+                expanded.push('%');
+                expanded.push_str(name);
+                rest = &rest[start + len + 1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Drop entries listed before, keeping the first, which is the one searched.
+pub fn dedup(entries: Vec<Entry>, syntax: Syntax) -> Vec<Entry> {
+    let mut seen = HashSet::new();
+    entries
+        .into_iter()
+        .filter(|entry| seen.insert(syntax.key(&entry.dir)))
+        .collect()
+}
+
+/// The entries of `PATH` in the environment of this process, or with `registry` of the machine
+/// and user `Path` as stored in the registry, which new processes start with.
+///
+/// Entries are de-duplicated but not checked for existence.
+pub fn entries(registry: bool) -> Result<Vec<Entry>> {
+    let lookup = |name: &str| std::env::var(name).ok();
+    let entries = if registry {
+        registry_entries(lookup)?
+    } else {
+        let value = std::env::var("PATH").map_err(|_| Error::PathNotFound)?;
+        Syntax::NATIVE
+            .parse(&value, lookup)
+            .into_iter()
+            .map(|dir| Entry {
+                dir,
+                source: Source::Process,
+            })
+            .collect()
+    };
+    Ok(dedup(entries, Syntax::NATIVE))
+}
+
+#[cfg(windows)]
+fn registry_entries(lookup: impl Fn(&str) -> Option<String>) -> Result<Vec<Entry>> {
+    use windows::Win32::System::Registry::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    const MACHINE: &str = r"SYSTEM\CurrentControlSet\Control\Session Manager\Environment";
+    const USER: &str = "Environment";
+
+    let mut entries = vec![];
+    // the user `Path` is appended to the machine one for new processes
+    for (key, subkey, source) in [
+        (HKEY_LOCAL_MACHINE, MACHINE, Source::Machine),
+        (HKEY_CURRENT_USER, USER, Source::User),
+    ] {
+        let Some(value) = registry_value(key, subkey) else {
+            continue;
+        };
+        entries.extend(
+            Syntax::WINDOWS
+                .parse(&value, &lookup)
+                .into_iter()
+                .map(|dir| Entry { dir, source }),
+        );
+    }
+    Ok(entries)
+}
+
+/// The `Path` value under `subkey`, unexpanded, `None` if there is none.
+#[cfg(windows)]
+fn registry_value(key: windows::Win32::System::Registry::HKEY, subkey: &str) -> Option<String> {
+    use windows::{
+        Win32::System::Registry::{
+            RRF_NOEXPAND, RRF_RT_REG_EXPAND_SZ, RRF_RT_REG_SZ, RegGetValueW,
+        },
+        core::{HSTRING, w},
+    };
+
+    let subkey = HSTRING::from(subkey);
+    // expanding is left to `Syntax::parse`, so that it is done once and the same way
+    let flags = RRF_RT_REG_SZ | RRF_RT_REG_EXPAND_SZ | RRF_NOEXPAND;
+    let mut size = 0u32;
+    unsafe { RegGetValueW(key, &subkey, w!("Path"), flags, None, None, Some(&mut size)) }
+        .ok()
+        .ok()?;
+    let mut buffer = vec![0u16; size as usize / 2];
+    unsafe {
+        RegGetValueW(
+            key,
+            &subkey,
+            w!("Path"),
+            flags,
+            None,
+            Some(buffer.as_mut_ptr().cast()),
+            Some(&mut size),
+        )
+    }
+    .ok()
+    .ok()?;
+    buffer.truncate(size as usize / 2);
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
+
+#[cfg(not(windows))]
+fn registry_entries(_lookup: impl Fn(&str) -> Option<String>) -> Result<Vec<Entry>> {
+    RegistryUnavailableSnafu.fail()
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("PATH environment variable not found"))]
+    PathNotFound,
+    #[snafu(display("the machine and user PATH can only be read from the registry on Windows"))]
+    RegistryUnavailable,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name.to_ascii_uppercase().as_str() {
+            "SYSTEMROOT" => Some(r"C:\Windows".to_string()),
+            "LOCALAPPDATA" => Some(r"C:\Users\me\AppData\Local".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let value = r#" %SystemRoot%\system32 ;"C:\Program Files\A;B\bin";;C:\Tools\;D:/x/y/;C:\;\\;\\\;\\server\share\;\;%LocalAppData%\Microsoft\WindowsApps;%MISSING%\bin;100%;50%"#;
+        assert_eq!(
+            Syntax::WINDOWS.parse(value, lookup),
+            [
+                r"C:\Windows\system32",
+                r"C:\Program Files\A;B\bin",
+                r"C:\Tools",
+                r"D:\x\y",
+                r"C:\",
+                r"\\",
+                r"\\",
+                r"\\server\share",
+                r"\",
+                r"C:\Users\me\AppData\Local\Microsoft\WindowsApps",
+                r"%MISSING%\bin",
+                r"100%",
+                r"50%",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(expand("%A%B%SystemRoot%", &lookup), r"%A%BC:\Windows");
+    }
+
+    #[test]
+    fn test_parse_unix() {
+        assert_eq!(
+            Syntax::UNIX.parse("/usr/bin:/usr/local/bin/::/:\"/a\":%HOME%", lookup),
+            ["/usr/bin", "/usr/local/bin", "/", "\"/a\"", "%HOME%"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_dedup() {
+        let entry = |dir: &str, source| Entry {
+            dir: PathBuf::from(dir),
+            source,
+        };
+        let entries = vec![
+            entry(r"C:\Windows\System32", Source::Machine),
+            entry(r"C:\Tools", Source::Machine),
+            entry(r"c:\windows\system32", Source::User),
+            entry(r"C:\Users\me\bin", Source::User),
+        ];
+        assert_eq!(
+            dedup(entries.clone(), Syntax::WINDOWS),
+            [entries[0].clone(), entries[1].clone(), entries[3].clone()]
+        );
+        assert_eq!(dedup(entries.clone(), Syntax::UNIX), entries);
+    }
+}
@@ -0,0 +1,148 @@
+//! Find a native build installed next to a non-native one.
+//!
+//! Vendors often ship every architecture side by side, as `bin\x64` and `bin\arm64`, an
+//! `arm64` folder beside the binaries, or another `Program Files` folder, while shortcuts and
+//! `PATH` point at the x64 build.
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    architecture::{Architecture, Compatibility, Host, Verdict},
+    detect,
+};
+
+/// How many directories up from the file an architecture folder is looked for.
+const MAX_DEPTH: usize = 3;
+
+const PROGRAM_FILES: &[&str] = &[
+    "Program Files",
+    "Program Files (x86)",
+    "Program Files (Arm)",
+];
+
+/// A binary with the file name of `path`, in the same install tree, that runs natively on
+/// `host`.
+pub fn find_native(path: &Path, host: &Host) -> Option<PathBuf> {
+    candidates(path, host.architecture)
+        .into_iter()
+        .find(|candidate| is_native(candidate, host))
+}
+
+/// Whether `path` is a binary that runs natively on `host`.
+pub fn is_native(path: &Path, host: &Host) -> bool {
+    path.is_file()
+        && detect::pe::detect_image_info_file(path, host.architecture).is_ok_and(|info| {
+            !info.is_architecture_neutral()
+                && Compatibility::of(&info, host).verdict == Verdict::Native
+        })
+}
+
+/// The paths a build of `path` for `host` would be installed at, most likely first.
+fn candidates(path: &Path, host: Architecture) -> Vec<PathBuf> {
+    let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return vec![];
+    };
+    let components = dir.components().collect::<Vec<_>>();
+    let mut candidates = vec![];
+
+    // `bin\x64\tool.exe` for `bin\arm64\tool.exe`
+    for index in (0..components.len()).rev().take(MAX_DEPTH) {
+        if let Component::Normal(name) = components[index]
+            && is_architecture_folder(name)
+        {
+            for host_name in host.names() {
+                let mut replaced = components.clone();
+                replaced[index] = Component::Normal(OsStr::new(host_name));
+                let dir = replaced.iter().collect::<PathBuf>();
+                candidates.push(dir.join(file_name));
+            }
+        }
+    }
+    // `tool.exe` for `arm64\tool.exe`, and `bin\tool.exe` for `arm64\tool.exe` beside `bin`
+    for base in dir.ancestors().take(2) {
+        for host_name in host.names() {
+            candidates.push(base.join(host_name).join(file_name));
+        }
+    }
+    // `Program Files (x86)\Vendor\tool.exe` for `Program Files\Vendor\tool.exe`
+    for (index, component) in components.iter().enumerate() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        if !PROGRAM_FILES.iter().any(|p| name.eq_ignore_ascii_case(p)) {
+            continue;
+        }
+        for other in PROGRAM_FILES
+            .iter()
+            .filter(|p| !name.eq_ignore_ascii_case(p))
+        {
+            let mut replaced = components.clone();
+            replaced[index] = Component::Normal(OsStr::new(other));
+            candidates.push(replaced.iter().collect::<PathBuf>().join(file_name));
+        }
+    }
+
+    // paths are case insensitive on Windows
+    let mut seen = HashSet::from([path.to_string_lossy().to_lowercase()]);
+    candidates.retain(|candidate| seen.insert(candidate.to_string_lossy().to_lowercase()));
+    candidates
+}
+
+fn is_architecture_folder(name: &OsStr) -> bool {
+    use strum::IntoEnumIterator;
+
+    Architecture::iter().any(|arch| arch.names().iter().any(|n| name.eq_ignore_ascii_case(n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let candidates = |path: &str| candidates(Path::new(path), Architecture::Arm64);
+
+        let found = candidates("/Vendor/bin/x64/tool.exe");
+        assert_eq!(found[0], Path::new("/Vendor/bin/arm64/tool.exe"));
+        assert_eq!(found[1], Path::new("/Vendor/bin/aarch64/tool.exe"));
+        assert!(found.contains(&PathBuf::from("/Vendor/bin/x64/arm64/tool.exe")));
+
+        let found = candidates("/Program Files (x86)/Vendor/bin/tool.exe");
+        assert!(found.contains(&PathBuf::from("/Program Files (x86)/Vendor/arm64/tool.exe")));
+        assert!(found.contains(&PathBuf::from("/Program Files/Vendor/bin/tool.exe")));
+        assert!(found.contains(&PathBuf::from("/Program Files (Arm)/Vendor/bin/tool.exe")));
+        assert!(!found.contains(&PathBuf::from("/Program Files (x86)/Vendor/bin/tool.exe")));
+
+        // architecture folders only count close to the file
+        let found = candidates("/x64/a/b/c/tool.exe");
+        assert!(!found.contains(&PathBuf::from("/arm64/a/b/c/tool.exe")));
+    }
+
+    #[test]
+    fn test_find_native() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+        let emulated = root.join("bin").join("x64").join("tool.exe");
+        let native = root.join("bin").join("arm64").join("tool.exe");
+        fs::create_dir_all(emulated.parent().unwrap()).unwrap();
+        fs::create_dir_all(native.parent().unwrap()).unwrap();
+        fs::copy(assets.join("testbin_x86_64-pc-windows-msvc.exe"), &emulated).unwrap();
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+
+        // a same-named binary of another architecture is no alternative
+        fs::copy(assets.join("testbin_i686-pc-windows-msvc.exe"), &native).unwrap();
+        assert_eq!(find_native(&emulated, &host), None);
+        fs::copy(assets.join("testbin_aarch64-pc-windows-msvc.exe"), &native).unwrap();
+        assert_eq!(find_native(&emulated, &host), Some(native));
+    }
+}
@@ -0,0 +1,89 @@
+//! Read the publisher of an executable from its Authenticode signature.
+
+use std::path::Path;
+
+/// The name of the signer of `path`, e.g. `Microsoft Windows`, if the file carries an embedded
+/// signature that verifies.
+///
+/// Revocation is not checked, as that may go to the network for every file. Files signed
+/// through a catalog only, as many inbox Windows binaries are, have no publisher here.
+#[cfg(windows)]
+pub fn publisher(path: &Path) -> Option<String> {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows::{
+        Win32::{
+            Foundation::HWND,
+            Security::{
+                Cryptography::{CERT_NAME_SIMPLE_DISPLAY_TYPE, CertGetNameStringW},
+                WinTrust::{
+                    WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_DATA_0,
+                    WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE,
+                    WTD_STATEACTION_VERIFY, WTD_UI_NONE, WTHelperGetProvSignerFromChain,
+                    WTHelperProvDataFromStateData, WinVerifyTrust,
+                },
+            },
+        },
+        core::PCWSTR,
+    };
+
+    let wide_path = path
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+    let mut file_info = WINTRUST_FILE_INFO {
+        cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR(wide_path.as_ptr()),
+        ..Default::default()
+    };
+    let mut data = WINTRUST_DATA {
+        cbStruct: size_of::<WINTRUST_DATA>() as u32,
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_NONE,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 {
+            pFile: &mut file_info,
+        },
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        ..Default::default()
+    };
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    let status =
+        unsafe { WinVerifyTrust(HWND::default(), &mut action, &mut data as *mut _ as *mut _) };
+
+    let publisher = (status == 0)
+        .then(|| unsafe {
+            let provider = WTHelperProvDataFromStateData(data.hWVTStateData);
+            if provider.is_null() {
+                return None;
+            }
+            let signer = WTHelperGetProvSignerFromChain(provider, 0, false, 0);
+            if signer.is_null() || (*signer).csCertChain == 0 {
+                return None;
+            }
+            let cert = (*(*signer).pasCertChain).pCert;
+            let mut name = [0u16; 256];
+            let len = CertGetNameStringW(
+                cert,
+                CERT_NAME_SIMPLE_DISPLAY_TYPE,
+                0,
+                None,
+                Some(&mut name),
+            );
+            // the length includes the terminating null, 1 is an empty name
+            (len > 1).then(|| String::from_utf16_lossy(&name[..len as usize - 1]))
+        })
+        .flatten();
+
+    // the state is allocated even when verification fails
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    unsafe { WinVerifyTrust(HWND::default(), &mut action, &mut data as *mut _ as *mut _) };
+    publisher
+}
+
+/// There is no trust provider to verify signatures with outside Windows.
+#[cfg(not(windows))]
+pub fn publisher(_path: &Path) -> Option<String> {
+    None
+}
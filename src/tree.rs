@@ -0,0 +1,219 @@
+//! Lay out processes as a tree of parents and children, for `--tree`.
+//!
+//! Parents are matched by PID alone, which the system reuses once a parent exits, so a process
+//! whose parent is missing, is itself, or is part of a cycle is taken as a root.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Fold subtrees where every process is native into their root.
+    pub collapse_native: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+    pub pid: u32,
+    pub parent_pid: Option<u32>,
+    /// Whether the process is native on every host.
+    pub native: bool,
+}
+
+/// A line of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// The index of the node in the slice laid out.
+    pub index: usize,
+    /// What to draw before the node, e.g. `│  └─ `.
+    pub prefix: String,
+    /// The number of native descendants folded into this line.
+    pub collapsed: usize,
+}
+
+/// The lines to draw `nodes` as, depth first, with roots and siblings in the order given.
+pub fn lines(nodes: &[Node], options: Options) -> Vec<Line> {
+    let indices = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (node.pid, index))
+        .collect::<HashMap<_, _>>();
+    let mut children = vec![vec![]; nodes.len()];
+    let mut roots = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let parent = node
+            .parent_pid
+            .filter(|&parent| parent != node.pid)
+            .and_then(|parent| indices.get(&parent));
+        match parent {
+            Some(&parent) => children[parent].push(index),
+            None => roots.push(index),
+        }
+    }
+    let mut tree = Tree {
+        nodes,
+        children,
+        options,
+        visited: vec![false; nodes.len()],
+        lines: vec![],
+    };
+    for root in roots {
+        tree.walk(root, String::new(), String::new());
+    }
+    // only cycles are left, each is entered at its first node
+    for index in 0..nodes.len() {
+        if !tree.visited[index] {
+            tree.walk(index, String::new(), String::new());
+        }
+    }
+    tree.lines
+}
+
+struct Tree<'a> {
+    nodes: &'a [Node],
+    children: Vec<Vec<usize>>,
+    options: Options,
+    visited: Vec<bool>,
+    lines: Vec<Line>,
+}
+
+impl Tree<'_> {
+    /// Draw `index` after `prefix`, and its children after `indent`.
+    fn walk(&mut self, index: usize, prefix: String, indent: String) {
+        self.visited[index] = true;
+        let mut collapsed = 0;
+        if self.options.collapse_native
+            && let Some(descendants) = self.native_descendants(index)
+        {
+            for &descendant in &descendants {
+                self.visited[descendant] = true;
+            }
+            collapsed = descendants.len();
+        }
+        self.lines.push(Line {
+            index,
+            prefix,
+            collapsed,
+        });
+        if collapsed > 0 {
+            return;
+        }
+        let children = self.children[index]
+            .iter()
+            .copied()
+            .filter(|&child| !self.visited[child])
+            .collect::<Vec<_>>();
+        for (position, &child) in children.iter().enumerate() {
+            // a child may have been reached through a cycle meanwhile
+            if self.visited[child] {
+                continue;
+            }
+            let last = position + 1 == children.len();
+            let (branch, continuation) = if last {
+                ("└─ ", "   ")
+            } else {
+                ("├─ ", "│  ")
+            };
+            self.walk(
+                child,
+                format!("{indent}{branch}"),
+                format!("{indent}{continuation}"),
+            );
+        }
+    }
+
+    /// The descendants of `index` not drawn yet, if it and all of them are native.
+    fn native_descendants(&self, index: usize) -> Option<Vec<usize>> {
+        let mut seen = self.visited.clone();
+        let mut descendants = vec![];
+        let mut stack = vec![index];
+        while let Some(index) = stack.pop() {
+            if !self.nodes[index].native {
+                return None;
+            }
+            for &child in &self.children[index] {
+                if !seen[child] {
+                    seen[child] = true;
+                    descendants.push(child);
+                    stack.push(child);
+                }
+            }
+        }
+        Some(descendants)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(pid: u32, parent_pid: u32, native: bool) -> Node {
+        Node {
+            pid,
+            parent_pid: Some(parent_pid),
+            native,
+        }
+    }
+
+    fn draw(nodes: &[Node], collapse_native: bool) -> Vec<String> {
+        lines(nodes, Options { collapse_native })
+            .into_iter()
+            .map(|line| {
+                let pid = nodes[line.index].pid;
+                match line.collapsed {
+                    0 => format!("{}{pid}", line.prefix),
+                    n => format!("{}{pid} +{n}", line.prefix),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let nodes = [
+            node(4, 0, true),
+            // a native launcher with emulated helpers, listed before it
+            node(20, 10, false),
+            node(10, 1, true),
+            node(21, 10, false),
+            node(30, 21, true),
+            // native all the way down
+            node(100, 4, true),
+            node(101, 100, true),
+            node(102, 101, true),
+            // its parent exited and the PID is its own now
+            node(7, 7, false),
+            // a cycle through reused PIDs
+            node(50, 51, false),
+            node(51, 50, true),
+        ];
+        assert_eq!(
+            draw(&nodes, false),
+            [
+                "4",
+                "└─ 100",
+                "   └─ 101",
+                "      └─ 102",
+                "10",
+                "├─ 20",
+                "└─ 21",
+                "   └─ 30",
+                "7",
+                "50",
+                "└─ 51",
+            ]
+        );
+        assert_eq!(
+            draw(&nodes, true),
+            [
+                "4 +3",
+                "10",
+                "├─ 20",
+                "└─ 21",
+                "   └─ 30",
+                "7",
+                "50",
+                "└─ 51"
+            ]
+        );
+    }
+}
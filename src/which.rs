@@ -0,0 +1,185 @@
+//! Resolve a command name through `PATH` and `PATHEXT` the way `cmd` does, to tell which file
+//! runs and which later ones it shadows.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    architecture::{Compatibility, Host, Verdict},
+    detect::{self, pe::ImageInfo},
+    executable,
+    search_path::{self, Source},
+};
+
+/// `PATHEXT` when the variable is not set, as on a default Windows installation.
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD;.VBS;.VBE;.JS;.JSE;.WSF;.WSH;.MSC";
+
+/// A file a command name resolves to.
+pub struct Candidate {
+    pub path: PathBuf,
+    /// The `PATH` the directory of the file is listed in.
+    pub source: Source,
+    /// `None` for files that are not PE images, such as `.cmd` scripts, which run through
+    /// their interpreter.
+    pub image: Option<detect::Result<(ImageInfo, Compatibility)>>,
+}
+
+impl Candidate {
+    pub fn verdict(&self) -> Option<Verdict> {
+        match &self.image {
+            Some(Ok((_, compatibility))) => Some(compatibility.verdict),
+            _ => None,
+        }
+    }
+}
+
+/// The extensions tried for a name without one, in order, from `PATHEXT`.
+pub fn extensions() -> Vec<String> {
+    std::env::var("PATHEXT")
+        .unwrap_or_else(|_| DEFAULT_PATHEXT.to_string())
+        .split(';')
+        .map(|ext| ext.trim().to_ascii_lowercase())
+        .filter(|ext| ext.starts_with('.') && ext.len() > 1)
+        .collect()
+}
+
+/// The file names tried for `name` in each directory, in order.
+///
+/// A name carrying one of `extensions` is only tried as is, others only with each extension
+/// appended, as `cmd` does.
+fn file_names(name: &str, extensions: &[String]) -> Vec<String> {
+    let has_extension = Path::new(name).extension().is_some_and(|ext| {
+        extensions.contains(&format!(".{}", ext.to_string_lossy()).to_lowercase())
+    });
+    if has_extension {
+        vec![name.to_string()]
+    } else {
+        extensions
+            .iter()
+            .map(|ext| format!("{name}{ext}"))
+            .collect()
+    }
+}
+
+/// Every file `name` resolves to through `dirs`, in the order they are searched, so the first
+/// one is what runs and the others are shadowed by it.
+///
+/// The current directory, which `cmd` searches before `PATH`, is not considered.
+pub fn resolve(
+    name: &str,
+    dirs: &[search_path::Entry],
+    extensions: &[String],
+    host: &Host,
+) -> Vec<Candidate> {
+    let file_names = file_names(name, extensions);
+    dirs.iter()
+        .flat_map(|entry| {
+            file_names
+                .iter()
+                .map(|file_name| (entry.dir.join(file_name), entry.source))
+        })
+        .filter(|(path, _)| path.is_file())
+        .map(|(path, source)| {
+            let is_image = executable::has_executable_extension(&path)
+                || path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("com"));
+            let image = is_image.then(|| {
+                detect::pe::detect_image_info_file(&path, host.architecture)
+                    .map(|info| (info, Compatibility::of(&info, host)))
+            });
+            Candidate {
+                path,
+                source,
+                image,
+            }
+        })
+        .collect()
+}
+
+/// A native build shadowed by the one that runs, when that one is emulated or runs under
+/// WOW64.
+///
+/// Scripts, unreadable files and builds that cannot run at all shadow nothing worth pointing
+/// out.
+pub fn shadowed_native(candidates: &[Candidate]) -> Option<&Candidate> {
+    let (first, rest) = candidates.split_first()?;
+    if !matches!(first.verdict(), Some(Verdict::Emulated | Verdict::Wow64)) {
+        return None;
+    }
+    rest.iter()
+        .find(|candidate| candidate.verdict() == Some(Verdict::Native))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::architecture::Architecture;
+
+    #[test]
+    fn test_file_names() {
+        let extensions = [".com", ".exe", ".cmd"].map(String::from);
+        assert_eq!(
+            file_names("git", &extensions),
+            ["git.com", "git.exe", "git.cmd"]
+        );
+        assert_eq!(file_names("git.EXE", &extensions), ["git.EXE"]);
+        // not an extension `cmd` knows, so one is still appended
+        assert_eq!(file_names("node.v20", &extensions)[1], "node.v20.exe");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_assets");
+        let dirs = ["emulated", "scripts", "native"].map(|dir| search_path::Entry {
+            dir: root.join(dir),
+            source: Source::Machine,
+        });
+        for entry in &dirs {
+            fs::create_dir_all(&entry.dir).unwrap();
+        }
+        fs::copy(
+            assets.join("testbin_x86_64-pc-windows-msvc.exe"),
+            dirs[0].dir.join("tool.exe"),
+        )
+        .unwrap();
+        fs::write(dirs[1].dir.join("tool.cmd"), "@echo off").unwrap();
+        fs::copy(
+            assets.join("testbin_aarch64-pc-windows-msvc.exe"),
+            dirs[2].dir.join("tool.exe"),
+        )
+        .unwrap();
+        let host = Host {
+            architecture: Architecture::Arm64,
+            build: 26100,
+        };
+        let extensions = [".exe", ".cmd"].map(String::from);
+
+        let candidates = resolve("tool", &dirs, &extensions, &host);
+        let paths = candidates
+            .iter()
+            .map(|c| c.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                dirs[0].dir.join("tool.exe"),
+                dirs[1].dir.join("tool.cmd"),
+                dirs[2].dir.join("tool.exe"),
+            ]
+        );
+        assert_eq!(candidates[0].verdict(), Some(Verdict::Emulated));
+        assert!(candidates[1].image.is_none());
+        assert_eq!(
+            shadowed_native(&candidates).map(|c| &c.path),
+            Some(&dirs[2].dir.join("tool.exe"))
+        );
+        // nothing is shadowed once the native build comes first, or by a script
+        assert!(shadowed_native(&candidates[2..]).is_none());
+        assert!(shadowed_native(&candidates[1..]).is_none());
+        assert!(resolve("missing", &dirs, &extensions, &host).is_empty());
+    }
+}